@@ -0,0 +1,90 @@
+use anthropic_sdk::Client;
+use claude_engineer_rs::tools::{EditInstruction, SearchMode, ToolExecutor};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::fs;
+use tempfile::tempdir;
+
+fn synthetic_source(lines: usize) -> String {
+    (0..lines)
+        .map(|i| match i % 4 {
+            0 => format!("fn function_{}() -> usize {{", i),
+            1 => format!("    let value_{} = {};", i, i),
+            2 => format!("    value_{}", i - 1),
+            _ => "}".to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn edit_instructions(lines: usize, edits: usize) -> Vec<EditInstruction> {
+    let step = (lines / 4 / edits).max(1) * 4;
+    (0..edits)
+        .map(|e| e * step)
+        .take_while(|&i| i + 1 < lines)
+        .map(|i| EditInstruction {
+            search: format!("    let value_{} = {};", i + 1, i + 1),
+            replace: format!("    let value_{} = {} * 2;", i + 1, i + 1),
+            search_mode: SearchMode::Exact,
+        })
+        .collect()
+}
+
+fn bench_apply_edits(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut executor = ToolExecutor::new(Client::new()).unwrap();
+    executor.set_auto_apply(true);
+    let temp_dir = tempdir().unwrap();
+
+    let mut group = c.benchmark_group("apply_edits");
+    group.sample_size(10);
+    for lines in [100, 1000, 10000] {
+        let original = synthetic_source(lines);
+        let path = temp_dir.path().join(format!("source_{}.rs", lines));
+        let path = path.to_str().unwrap().to_string();
+        for edits in [1, 10, 50] {
+            group.throughput(Throughput::Elements(edits as u64));
+            group.bench_with_input(
+                BenchmarkId::new(format!("{}_lines", lines), edits),
+                &edits,
+                |b, &edits| {
+                    let executor = &executor;
+                    let path = path.as_str();
+                    let original = original.as_str();
+                    b.to_async(&runtime).iter(|| async move {
+                        fs::write(path, original).unwrap();
+                        executor
+                            .apply_edits(path, edit_instructions(lines, edits), original)
+                            .await
+                            .unwrap()
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn bench_highlight_diff(c: &mut Criterion) {
+    let executor = ToolExecutor::new(Client::new()).unwrap();
+
+    let mut group = c.benchmark_group("highlight_diff");
+    for lines in [100, 1000, 10000] {
+        let diff_text = synthetic_source(lines)
+            .lines()
+            .enumerate()
+            .map(|(i, line)| match i % 10 {
+                0 => format!("-{}\n", line),
+                1 => format!("+{}\n", line),
+                _ => format!(" {}\n", line),
+            })
+            .collect::<String>();
+        group.throughput(Throughput::Elements(lines as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(lines), &diff_text, |b, diff| {
+            b.iter(|| executor.highlight_diff(diff))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_apply_edits, bench_highlight_diff);
+criterion_main!(benches);
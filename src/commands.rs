@@ -0,0 +1,107 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Continue,
+    Exit,
+    NewPrompt,
+    Branch,
+    Search,
+    Status,
+    Undo,
+    Revert,
+    Save,
+    Model(String),
+    Checkpoint(String),
+    Restore(String),
+    Tools,
+    History,
+    Clear,
+    Help,
+    Unknown(String),
+}
+
+pub struct CommandParser;
+
+impl CommandParser {
+    pub fn parse(input: &str) -> Command {
+        let input = input.trim();
+        let (name, argument) = match input.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (input, ""),
+        };
+
+        match (name.to_lowercase().as_str(), argument) {
+            ("c", "") => Command::Continue,
+            ("e", "") => Command::Exit,
+            ("n", "") => Command::NewPrompt,
+            ("branch", "") => Command::Branch,
+            ("search", "") => Command::Search,
+            ("/status", "") => Command::Status,
+            ("/undo", "") => Command::Undo,
+            ("/revert", "") => Command::Revert,
+            ("/save", "") => Command::Save,
+            ("/model", model) if !model.is_empty() => Command::Model(model.to_string()),
+            ("/checkpoint", name) if !name.is_empty() => Command::Checkpoint(name.to_string()),
+            ("/restore", name) if !name.is_empty() => Command::Restore(name.to_string()),
+            ("/tools", "") => Command::Tools,
+            ("/history", "") => Command::History,
+            ("/clear", "") => Command::Clear,
+            ("/help", "") | ("help", "") => Command::Help,
+            _ => Command::Unknown(input.to_string()),
+        }
+    }
+
+    pub fn help() -> &'static str {
+        r#"
+                Starting a new iteration. How would you like to proceed?
+                c: Continue from the last response
+                e: Exit the program
+                n: Input a new prompt
+                branch: Save this conversation and continue on a new branch of it
+                search: Find earlier messages matching a query
+                /status: Show token usage and session stats
+                /undo: Restore the last edited file
+                /revert: Restore every file changed during this session
+                /save: Save the chat to a Markdown file
+                /model <name>: Switch the model used for the rest of the session
+                /checkpoint <name>: Mark the current point in the conversation
+                /restore <name>: Rewind the conversation to a checkpoint
+                /tools: List the available tools
+                /history: Show the last 5 conversation exchanges
+                /clear: Clear the conversation history
+            "#
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(CommandParser::parse("c\n"), Command::Continue);
+        assert_eq!(CommandParser::parse("E"), Command::Exit);
+        assert_eq!(CommandParser::parse("branch"), Command::Branch);
+        assert_eq!(CommandParser::parse("/status"), Command::Status);
+        assert_eq!(CommandParser::parse("/revert"), Command::Revert);
+        assert_eq!(
+            CommandParser::parse("/model claude-3-haiku-20240307"),
+            Command::Model("claude-3-haiku-20240307".to_string())
+        );
+        assert_eq!(
+            CommandParser::parse("/model"),
+            Command::Unknown("/model".to_string())
+        );
+        assert_eq!(
+            CommandParser::parse("/checkpoint planning"),
+            Command::Checkpoint("planning".to_string())
+        );
+        assert_eq!(
+            CommandParser::parse("/restore planning"),
+            Command::Restore("planning".to_string())
+        );
+        assert_eq!(
+            CommandParser::parse("what"),
+            Command::Unknown("what".to_string())
+        );
+    }
+}
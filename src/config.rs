@@ -0,0 +1,238 @@
+use crate::conversation_manager::{ChatSaveTemplate, ExportFormat};
+use crate::prompts::EditorPromptVersion;
+use crate::tools::{ConflictResolution, DiffDisplayMode};
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const DEFAULT_CHAT_FILENAME_FORMAT: &str = "Chat_{timestamp}.md";
+pub const DEFAULT_MODEL: &str = "claude-3-5-sonnet-20240620";
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub chat_filename_format: String,
+    pub clipboard: bool,
+    pub max_file_size_kb: usize,
+    /// Maximum number of tokens requested for each response.
+    pub max_tokens: i32,
+    /// Maximum number of messages kept in conversation history.
+    pub max_history: usize,
+    /// Conversation token count above which the oldest history is summarized.
+    pub token_budget: usize,
+    /// Apply generated edits without asking for confirmation.
+    pub auto_apply: bool,
+    /// Report file system changes without writing them.
+    pub dry_run: bool,
+    /// Maximum file system reads per second. `f64::INFINITY` disables the limit.
+    pub read_ops_per_second: f64,
+    /// Maximum file system writes per second. `f64::INFINITY` disables the limit.
+    pub write_ops_per_second: f64,
+    /// Files with at least this many lines have edit instructions generated in windows.
+    pub batch_edit_min_lines: usize,
+    /// Allow ANSI-colored tool output. Disabled when `NO_COLOR` is set.
+    pub use_color: bool,
+    /// Anthropic beta features sent with every request.
+    pub beta_flags: Vec<String>,
+    /// How long results of read-only tools are reused within a session.
+    pub cache_ttl: Duration,
+    pub editor_prompt_version: EditorPromptVersion,
+    /// Directory for per-session file backups. Backups are disabled when unset.
+    pub backup_dir: Option<PathBuf>,
+    /// Directory file tools are confined to. Paths are not restricted when unset.
+    pub working_dir: Option<PathBuf>,
+    /// File audit events, such as the editor prompt version used for each edit, are
+    /// appended to. Audit events only go to the `audit` log target when unset.
+    pub audit_log: Option<PathBuf>,
+    /// Allow paths through symlinks whose target stays inside `working_dir`.
+    pub follow_symlinks: bool,
+    /// Layout of chats saved with `/save`.
+    pub chat_template: ChatSaveTemplate,
+    /// Format of chats saved with `/save` and on exit.
+    pub export_format: ExportFormat,
+    /// Maximum calls per tool in a session, e.g. `{"read_file": 50}`. Tools not listed
+    /// are unlimited.
+    pub tool_use_count_limit: HashMap<String, usize>,
+    /// How diffs are shown before edits are applied.
+    pub display_mode: DiffDisplayMode,
+    /// What to do with an edit that overlaps an earlier edit in the same batch.
+    pub edit_conflict_resolution: ConflictResolution,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            chat_filename_format: DEFAULT_CHAT_FILENAME_FORMAT.to_string(),
+            clipboard: false,
+            max_file_size_kb: 1024,
+            max_tokens: 4000,
+            max_history: 1000,
+            token_budget: 150_000,
+            auto_apply: false,
+            dry_run: false,
+            read_ops_per_second: f64::INFINITY,
+            write_ops_per_second: f64::INFINITY,
+            batch_edit_min_lines: 500,
+            use_color: std::env::var_os("NO_COLOR").is_none(),
+            beta_flags: vec!["prompt-caching-2024-07-31".to_string()],
+            cache_ttl: Duration::from_secs(30),
+            editor_prompt_version: EditorPromptVersion::default(),
+            backup_dir: None,
+            working_dir: None,
+            audit_log: None,
+            follow_symlinks: false,
+            chat_template: ChatSaveTemplate::default(),
+            export_format: ExportFormat::default(),
+            tool_use_count_limit: HashMap::new(),
+            display_mode: DiffDisplayMode::default(),
+            edit_conflict_resolution: ConflictResolution::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Builds a config from `CLAUDE_*` environment variables, falling back to the
+    /// defaults for any that are unset. See CONFIGURATION.md.
+    pub fn from_env() -> Result<Self> {
+        let defaults = Self::default();
+        Ok(Self {
+            max_tokens: env_or("CLAUDE_MAX_TOKENS", defaults.max_tokens)?,
+            max_history: env_or("CLAUDE_MAX_HISTORY", defaults.max_history)?,
+            token_budget: env_or("CLAUDE_TOKEN_BUDGET", defaults.token_budget)?,
+            auto_apply: env_flag_or("CLAUDE_AUTO_APPLY", defaults.auto_apply)?,
+            dry_run: env_flag_or("CLAUDE_DRY_RUN", defaults.dry_run)?,
+            export_format: env_or("CLAUDE_EXPORT_FORMAT", defaults.export_format)?,
+            chat_filename_format: env_or(
+                "CLAUDE_CHAT_FILENAME_FORMAT",
+                defaults.chat_filename_format,
+            )?,
+            working_dir: std::env::var_os("CLAUDE_WORKING_DIR").map(PathBuf::from),
+            audit_log: std::env::var_os("CLAUDE_AUDIT_LOG").map(PathBuf::from),
+            follow_symlinks: env_flag_or("CLAUDE_FOLLOW_SYMLINKS", defaults.follow_symlinks)?,
+            tool_use_count_limit: match std::env::var("CLAUDE_TOOL_USE_LIMITS") {
+                Ok(value) => parse_tool_use_limits(&value)?,
+                Err(_) => defaults.tool_use_count_limit,
+            },
+            display_mode: env_or("CLAUDE_DISPLAY_MODE", defaults.display_mode)?,
+            edit_conflict_resolution: env_or(
+                "CLAUDE_EDIT_CONFLICT",
+                defaults.edit_conflict_resolution,
+            )?,
+            ..defaults
+        })
+    }
+}
+
+/// The model named by `CLAUDE_MODEL`, or `DEFAULT_MODEL` if it is unset.
+pub fn model_from_env() -> String {
+    std::env::var("CLAUDE_MODEL").unwrap_or_else(|_| DEFAULT_MODEL.to_string())
+}
+
+/// Parses per-tool call limits written as `read_file=50,edit_and_apply=10`.
+fn parse_tool_use_limits(value: &str) -> Result<HashMap<String, usize>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (tool, limit) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid value for CLAUDE_TOOL_USE_LIMITS: {}", entry))?;
+            let limit = limit
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("Invalid value for CLAUDE_TOOL_USE_LIMITS: {}", entry))?;
+            Ok((tool.trim().to_string(), limit))
+        })
+        .collect()
+}
+
+const API_KEY_PREFIX: &str = "sk-ant-";
+const API_KEY_MIN_LEN: usize = 40;
+
+/// Checks that `key` looks like an Anthropic API key, so a mistyped key is reported at
+/// startup rather than on the first request.
+pub fn validate_api_key(key: &str) -> Result<()> {
+    let problem = if !key.starts_with(API_KEY_PREFIX) {
+        Some(format!("it should start with \"{}\"", API_KEY_PREFIX))
+    } else if key.len() < API_KEY_MIN_LEN {
+        Some(format!("it is shorter than {} characters", API_KEY_MIN_LEN))
+    } else if !key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Some("it contains characters other than letters, digits, '-' and '_'".to_string())
+    } else {
+        None
+    };
+
+    match problem {
+        Some(problem) => Err(anyhow!(
+            "ANTHROPIC_API_KEY_RS does not look like a valid Anthropic API key: {}. Create a key at https://console.anthropic.com/settings/keys",
+            problem
+        )),
+        None => Ok(()),
+    }
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> Result<T> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("Invalid value for {}: {}", name, value)),
+        Err(_) => Ok(default),
+    }
+}
+
+fn env_flag_or(name: &str, default: bool) -> Result<bool> {
+    match std::env::var(name) {
+        Ok(value) => {
+            parse_flag(&value).ok_or_else(|| anyhow!("Invalid value for {}: {}", name, value))
+        }
+        Err(_) => Ok(default),
+    }
+}
+
+fn parse_flag(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" | "" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_flag() {
+        assert_eq!(parse_flag("TRUE"), Some(true));
+        assert_eq!(parse_flag("1"), Some(true));
+        assert_eq!(parse_flag("off"), Some(false));
+        assert_eq!(parse_flag("maybe"), None);
+    }
+
+    #[test]
+    fn test_parse_tool_use_limits() {
+        let limits = parse_tool_use_limits("read_file=50, edit_and_apply = 10,").unwrap();
+        assert_eq!(limits.len(), 2);
+        assert_eq!(limits["read_file"], 50);
+        assert_eq!(limits["edit_and_apply"], 10);
+        assert!(parse_tool_use_limits("").unwrap().is_empty());
+        assert!(parse_tool_use_limits("read_file").is_err());
+        assert!(parse_tool_use_limits("read_file=many").is_err());
+    }
+
+    #[test]
+    fn test_validate_api_key() {
+        let valid = format!("sk-ant-api03-{}", "a1B2_c3D4-".repeat(4));
+        assert!(validate_api_key(&valid).is_ok());
+        assert!(validate_api_key("sk-ant-short").is_err());
+        assert!(validate_api_key(&valid.replacen("sk-ant-", "sk-xyz-", 1)).is_err());
+        assert!(validate_api_key(&format!("{} ", valid)).is_err());
+        assert!(validate_api_key("").is_err());
+    }
+}
@@ -1,21 +1,254 @@
-use chrono::Local;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, Utc};
 use log::{debug, info, trace, warn};
+use regex::RegexBuilder;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::collections::VecDeque;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
+use std::path::Path;
+use uuid::Uuid;
+
+use crate::config::DEFAULT_CHAT_FILENAME_FORMAT;
+use crate::store::ConversationStore;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub role: String,
     pub content: MessageContent,
+    /// Input tokens attributed to this message by `record_input_tokens`, or 0 if not
+    /// counted yet. Never sent to the API.
+    #[serde(default, skip_serializing)]
+    pub token_count: usize,
+}
+
+impl Message {
+    pub fn new(role: &str, content: MessageContent) -> Self {
+        Self {
+            role: role.to_string(),
+            content,
+            token_count: 0,
+        }
+    }
+
+    pub fn text(role: &str, text: impl Into<String>) -> Self {
+        Self::new(role, MessageContent::Text(text.into()))
+    }
+}
+
+pub const ROLE_ALIGNMENT_PLACEHOLDER: &str = "Continue.";
+pub const TOOL_RESULT_PLACEHOLDER: &str = "[tool result omitted]";
+/// Starts the message that replaces history condensed by `replace_oldest_with_summary`.
+pub const SUMMARY_PREFIX: &str = "[SUMMARY]";
+/// Assistant reply following the summary, so the user prompt after it keeps roles
+/// alternating.
+pub const SUMMARY_ACKNOWLEDGEMENT: &str = "Understood. I'll continue from that summary.";
+
+/// Returns the indices of messages that repeat the role of the message before them.
+pub fn validate_role_alignment(messages: &[Message]) -> Result<(), Vec<usize>> {
+    let violations = messages
+        .windows(2)
+        .enumerate()
+        .filter(|(_, pair)| pair[0].role == pair[1].role)
+        .map(|(i, _)| i + 1)
+        .collect::<Vec<_>>();
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+/// Rough token estimate for a conversation, assuming ~4 characters per token.
+pub fn estimate_tokens(messages: &[Message]) -> usize {
+    messages
+        .iter()
+        .map(|message| message.content.to_plain_text().len() / 4 + 1)
+        .sum()
+}
+
+fn is_user_prompt(message: &Message) -> bool {
+    message.role == "user" && matches!(message.content, MessageContent::Text(_))
+}
+
+/// Whether `message` repeats `previous`: the same role with identical text, or identical
+/// tool results for the same tool calls.
+fn is_repeat_of(previous: &Message, message: &Message) -> bool {
+    if previous.role != message.role {
+        return false;
+    }
+    match (&previous.content, &message.content) {
+        (MessageContent::Text(a), MessageContent::Text(b)) => a == b,
+        (MessageContent::ToolUseUser(a), MessageContent::ToolUseUser(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|(a, b)| a.tool_use_id == b.tool_use_id && a.content == b.content)
+        }
+        _ => false,
+    }
+}
+
+/// Headings and options used when saving a chat to Markdown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatSaveTemplate {
+    pub title: String,
+    pub user_heading: String,
+    pub assistant_heading: String,
+    /// Prefix of the heading for each tool call or result, followed by the tool name.
+    pub tool_heading: String,
+    /// Adds the time the chat was saved below the title. Messages carry no timestamps
+    /// of their own.
+    pub include_timestamps: bool,
+}
+
+impl Default for ChatSaveTemplate {
+    fn default() -> Self {
+        Self {
+            title: "Claude-3-Sonnet Engineer Chat Log".to_string(),
+            user_heading: "User".to_string(),
+            assistant_heading: "Claude".to_string(),
+            tool_heading: "Tool Use".to_string(),
+            include_timestamps: false,
+        }
+    }
+}
+
+/// File format `Claude::save_chat` writes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Markdown,
+    /// A self-contained page with collapsible tool calls.
+    Html,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format.to_lowercase().as_str() {
+            "markdown" | "md" => Ok(ExportFormat::Markdown),
+            "html" => Ok(ExportFormat::Html),
+            _ => Err(anyhow!("Unknown export format: {}", format)),
+        }
+    }
+}
+
+const CHAT_HTML_STYLE: &str =
+    "body { font-family: sans-serif; max-width: 50rem; margin: 2rem auto; line-height: 1.5; }
+h2 { border-bottom: 1px solid #ddd; }
+pre { background: #f6f8fa; padding: 0.75rem; overflow-x: auto; }
+code { font-family: monospace; }
+details { margin: 0.5rem 0; }
+summary { cursor: pointer; font-weight: bold; }";
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Renders message text as paragraphs, turning fenced code blocks into
+/// `<pre><code class="language-...">`. Unlabelled blocks are assumed to be Rust.
+fn text_to_html(text: &str) -> String {
+    let mut html = String::new();
+    for (i, part) in text.split("```").enumerate() {
+        if i % 2 == 1 {
+            let (language, code) = part.split_once('\n').unwrap_or(("", part));
+            let language = match language.trim() {
+                "" => "rust",
+                language => language,
+            };
+            html.push_str(&format!(
+                "<pre><code class=\"language-{}\">{}</code></pre>\n",
+                escape_html(language),
+                escape_html(code.trim_end())
+            ));
+        } else {
+            for paragraph in part.split("\n\n").filter(|p| !p.trim().is_empty()) {
+                html.push_str(&format!("<p>{}</p>\n", escape_html(paragraph.trim())));
+            }
+        }
+    }
+    html
+}
+
+fn tool_block_html(heading: &str, content: &str) -> String {
+    format!(
+        "<details>\n<summary>{}</summary>\n<pre><code class=\"language-json\">{}</code></pre>\n</details>\n",
+        escape_html(heading),
+        escape_html(content)
+    )
+}
+
+fn write_chat_file(path: &str, content: &str) -> std::io::Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    let mut file = File::create(path)?;
+    file.write_all(content.as_bytes())?;
+    info!("Chat saved to file: {}", path);
+    Ok(())
+}
+
+/// Which history message is dropped when history is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Drop the oldest message.
+    #[default]
+    Oldest,
+    /// Drop the message that was least recently sent to the model.
+    LeastRecentlyAccessed,
 }
 
-#[derive(Debug, Clone)]
+/// The part of a `ConversationManager` written by `save_session`.
+#[derive(Serialize, Deserialize)]
+struct SavedSession {
+    max_history_size: usize,
+    history: Vec<Message>,
+    current: Vec<Message>,
+    /// Missing from sessions saved before branching was added.
+    #[serde(default)]
+    branch_id: Option<Uuid>,
+    #[serde(default)]
+    parent_id: Option<Uuid>,
+    #[serde(default)]
+    session_name: Option<String>,
+    #[serde(default = "Utc::now")]
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
 pub struct ConversationManager {
     history: VecDeque<Message>,
+    /// Logical time each history message was last accessed, parallel to `history`.
+    history_access: VecDeque<u64>,
+    access_clock: u64,
+    eviction_policy: EvictionPolicy,
     current: Vec<Message>,
     max_history_size: usize,
+    chat_filename_format: String,
+    persona: Option<String>,
+    session_id: Uuid,
+    /// Used in place of the timestamp in saved filenames when set.
+    session_name: Option<String>,
+    created_at: DateTime<Utc>,
+    branch_id: Uuid,
+    /// The branch this one was created from by `branch`.
+    parent_id: Option<Uuid>,
+    /// Named positions in the combined conversation, in creation order.
+    checkpoints: Vec<(String, usize)>,
+    /// History messages trimmed from the last request by
+    /// `get_combined_conversation_within_budget`.
+    trimmed_history: usize,
+    chat_template: ChatSaveTemplate,
+    /// Receives every message of each exchange once it is committed to history, when set.
+    store: Option<Box<dyn ConversationStore>>,
 }
 
 impl ConversationManager {
@@ -26,18 +259,184 @@ impl ConversationManager {
         );
         Self {
             history: VecDeque::new(),
+            history_access: VecDeque::new(),
+            access_clock: 0,
+            eviction_policy: EvictionPolicy::default(),
             current: Vec::new(),
             max_history_size,
+            chat_filename_format: DEFAULT_CHAT_FILENAME_FORMAT.to_string(),
+            persona: None,
+            session_id: Uuid::new_v4(),
+            session_name: None,
+            created_at: Utc::now(),
+            branch_id: Uuid::new_v4(),
+            parent_id: None,
+            checkpoints: Vec::new(),
+            trimmed_history: 0,
+            chat_template: ChatSaveTemplate::default(),
+            store: None,
+        }
+    }
+
+    pub fn session_id(&self) -> Uuid {
+        self.session_id
+    }
+
+    pub fn set_session_id(&mut self, session_id: Uuid) {
+        self.session_id = session_id;
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        info!("Setting session name: {}", name);
+        self.session_name = Some(name.to_string());
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.session_name.as_deref()
+    }
+
+    pub fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    pub fn branch_id(&self) -> Uuid {
+        self.branch_id
+    }
+
+    pub fn parent_id(&self) -> Option<Uuid> {
+        self.parent_id
+    }
+
+    /// Starts a new branch of the conversation with a copy of this one's history and
+    /// an empty current exchange, leaving this branch untouched. Checkpoints and the
+    /// conversation store are not carried over.
+    pub fn branch(&self) -> ConversationManager {
+        let branch = Self {
+            history: self.history.clone(),
+            history_access: self.history_access.clone(),
+            access_clock: self.access_clock,
+            eviction_policy: self.eviction_policy,
+            current: Vec::new(),
+            max_history_size: self.max_history_size,
+            chat_filename_format: self.chat_filename_format.clone(),
+            persona: self.persona.clone(),
+            session_id: self.session_id,
+            session_name: self.session_name.clone(),
+            created_at: Utc::now(),
+            branch_id: Uuid::new_v4(),
+            parent_id: Some(self.branch_id),
+            checkpoints: Vec::new(),
+            trimmed_history: 0,
+            chat_template: self.chat_template.clone(),
+            store: None,
+        };
+        info!(
+            "Created branch {} from branch {}",
+            branch.branch_id, self.branch_id
+        );
+        branch
+    }
+
+    pub fn set_persona(&mut self, persona: &str) {
+        info!("Setting persona: {}", persona);
+        self.persona = Some(persona.to_string());
+    }
+
+    pub fn set_eviction_policy(&mut self, eviction_policy: EvictionPolicy) {
+        self.eviction_policy = eviction_policy;
+    }
+
+    pub fn set_chat_filename_format(&mut self, format: &str) {
+        info!("Setting chat filename format: {}", format);
+        self.chat_filename_format = format.to_string();
+    }
+
+    pub fn set_chat_template(&mut self, template: ChatSaveTemplate) {
+        self.chat_template = template;
+    }
+
+    /// Saves each message committed with `commit_current_to_history` to `store` under
+    /// this session's ID. Exchanges that are cleared before being committed, such as a
+    /// retried request, are never saved.
+    pub fn set_store(&mut self, store: Box<dyn ConversationStore>) {
+        self.store = Some(store);
+    }
+
+    /// The session name with every character that isn't alphanumeric, `-` or `_`
+    /// replaced by `_`, so it can be used in a filename.
+    fn filename_safe_name(&self) -> Option<String> {
+        self.session_name.as_ref().map(|name| {
+            name.chars()
+                .map(|c| {
+                    if c.is_alphanumeric() || c == '-' || c == '_' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect()
+        })
+    }
+
+    /// `Chat_<name>_<HHMM>.md` for named sessions, otherwise `chat_filename_format`
+    /// with its placeholders filled in.
+    fn chat_filename(&self) -> String {
+        let now = Local::now();
+        if let Some(name) = self.filename_safe_name() {
+            return format!("Chat_{}_{}.md", name, now.format("%H%M"));
         }
+        self.chat_filename_format
+            .replace("{timestamp}", &now.format("%Y%m%d_%H%M%S").to_string())
+            .replace("{date}", &now.format("%Y%m%d").to_string())
+            .replace("{time}", &now.format("%H%M%S").to_string())
     }
 
     pub fn add_to_history(&mut self, message: Message) {
         if self.history.len() >= self.max_history_size {
-            let removed = self.history.pop_front();
-            info!("Removed oldest message from history: {:?}", removed);
+            let index = match self.eviction_policy {
+                EvictionPolicy::Oldest => 0,
+                EvictionPolicy::LeastRecentlyAccessed => self
+                    .history_access
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, accessed)| **accessed)
+                    .map_or(0, |(index, _)| index),
+            };
+            self.history_access.remove(index);
+            let removed = self.history.remove(index);
+            self.splice_checkpoints(index, 1, 0);
+            info!("Evicted message {} from history: {:?}", index, removed);
         }
         info!("Adding message to history: {:?}", message);
         self.history.push_back(message);
+        self.access_clock += 1;
+        self.history_access.push_back(self.access_clock);
+    }
+
+    /// Combines two sessions by appending `other`'s history to this one's, evicting
+    /// messages once `max_history_size` is reached. This session's current exchange and
+    /// checkpoints are kept. Messages carry no timestamps, so `other`'s history follows
+    /// this one's rather than being interleaved.
+    pub fn merge(mut self, other: ConversationManager) -> Self {
+        info!(
+            "Merging {} history messages from session {} into {}",
+            other.history.len(),
+            other.session_id,
+            self.session_id
+        );
+        for message in other.history {
+            self.add_to_history(message);
+        }
+        self
+    }
+
+    /// Marks the history message at `index` as just accessed, protecting it from
+    /// `EvictionPolicy::LeastRecentlyAccessed`.
+    pub fn access_message(&mut self, index: usize) {
+        if let Some(accessed) = self.history_access.get_mut(index) {
+            self.access_clock += 1;
+            *accessed = self.access_clock;
+        }
     }
 
     pub fn add_to_current(&mut self, message: Message) {
@@ -45,6 +444,209 @@ impl ConversationManager {
         self.current.push(message);
     }
 
+    pub fn clear_history(&mut self) {
+        info!("Clearing conversation history");
+        self.splice_checkpoints(0, self.history.len(), 0);
+        self.history.clear();
+        self.history_access.clear();
+    }
+
+    /// Replaces all but the last `keep_last_n` tool result messages in history with a
+    /// short placeholder, keeping the conversational thread while dropping bulky output.
+    pub fn prune_tool_results(&mut self, keep_last_n: usize) {
+        let tool_result_indices = self
+            .history
+            .iter()
+            .enumerate()
+            .filter(|(_, message)| matches!(message.content, MessageContent::ToolUseUser(_)))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let prune_count = tool_result_indices.len().saturating_sub(keep_last_n);
+        for &index in &tool_result_indices[..prune_count] {
+            self.history[index].content = MessageContent::Text(TOOL_RESULT_PLACEHOLDER.to_string());
+        }
+        info!("Pruned {} tool results from history", prune_count);
+    }
+
+    /// Returns up to `count` of the oldest history messages.
+    pub fn oldest_history(&self, count: usize) -> Vec<Message> {
+        self.history.iter().take(count).cloned().collect()
+    }
+
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// The index of the first user prompt in history at or after `index`, or the
+    /// history length if there is none. Cutting history there never separates a tool
+    /// result from its tool call.
+    pub fn next_turn_start(&self, index: usize) -> usize {
+        (index..self.history.len())
+            .find(|&i| is_user_prompt(&self.history[i]))
+            .unwrap_or(self.history.len())
+    }
+
+    /// Replaces the oldest history messages, `count` moved forward to
+    /// `next_turn_start`, with a user message holding `summary`, prefixed with
+    /// `SUMMARY_PREFIX`, and an assistant message acknowledging it.
+    pub fn replace_oldest_with_summary(&mut self, count: usize, summary: &str) {
+        let count = self.next_turn_start(count);
+        self.history.drain(..count);
+        self.history_access.drain(..count);
+        self.splice_checkpoints(0, count, 2);
+        self.history
+            .push_front(Message::text("assistant", SUMMARY_ACKNOWLEDGEMENT));
+        self.history.push_front(Message::text(
+            "user",
+            format!("{} {}", SUMMARY_PREFIX, summary),
+        ));
+        self.access_clock += 1;
+        self.history_access.push_front(self.access_clock);
+        self.history_access.push_front(self.access_clock);
+        info!("Replaced {} history messages with a summary", count);
+    }
+
+    /// Returns the text messages in history and the current exchange that match the
+    /// regular expression `query`, with their index in the combined conversation. A
+    /// query that is not a valid regex is matched literally.
+    pub fn search_history(&self, query: &str, case_sensitive: bool) -> Vec<(usize, &Message)> {
+        let build = |pattern: &str| {
+            RegexBuilder::new(pattern)
+                .case_insensitive(!case_sensitive)
+                .build()
+        };
+        let Ok(regex) = build(query).or_else(|_| build(&regex::escape(query))) else {
+            return Vec::new();
+        };
+        self.history
+            .iter()
+            .chain(&self.current)
+            .enumerate()
+            .filter(|(_, message)| match &message.content {
+                MessageContent::Text(text) => regex.is_match(text),
+                _ => false,
+            })
+            .collect()
+    }
+
+    pub fn filter_by_role(&self, role: &str) -> Vec<&Message> {
+        self.history
+            .iter()
+            .chain(&self.current)
+            .filter(|message| message.role == role)
+            .collect()
+    }
+
+    pub fn message_count(&self) -> usize {
+        self.history.len() + self.current.len()
+    }
+
+    /// Sum of the token counts of every message in history and the current exchange.
+    pub fn total_token_count(&self) -> usize {
+        self.history
+            .iter()
+            .chain(&self.current)
+            .map(|message| message.token_count)
+            .sum()
+    }
+
+    /// Back-fills token counts from the `input_tokens` the API reported for a request.
+    /// The tokens not yet attributed to a message are split across the messages added
+    /// since the last call, with any rounding remainder going to the newest. The first
+    /// call's count also covers the system prompt and tool definitions. Only the messages
+    /// sent in the request are considered, so history trimmed to fit the token budget
+    /// neither counts against `input_tokens` nor receives a share of it.
+    pub fn record_input_tokens(&mut self, input_tokens: usize) {
+        let skip = self.trimmed_history.min(self.history.len());
+        let sent = self
+            .history
+            .iter_mut()
+            .skip(skip)
+            .chain(self.current.iter_mut())
+            .collect::<Vec<_>>();
+        let counted = sent
+            .iter()
+            .map(|message| message.token_count)
+            .sum::<usize>();
+        let uncounted = input_tokens.saturating_sub(counted);
+        let mut new_messages = sent
+            .into_iter()
+            .filter(|message| message.token_count == 0)
+            .collect::<Vec<_>>();
+        let Some(count) = std::num::NonZeroUsize::new(new_messages.len()) else {
+            return;
+        };
+        let share = uncounted / count.get();
+        for message in new_messages.iter_mut() {
+            message.token_count = share;
+        }
+        if let Some(newest) = new_messages.last_mut() {
+            newest.token_count += uncounted % count.get();
+        }
+        debug!(
+            "Attributed {} input tokens to {} new messages",
+            uncounted, count
+        );
+    }
+
+    /// Records the current length of the conversation under `name`, replacing any
+    /// checkpoint with the same name, and returns that position.
+    pub fn create_checkpoint(&mut self, name: &str) -> usize {
+        let position = self.history.len() + self.current.len();
+        self.checkpoints.retain(|(existing, _)| existing != name);
+        self.checkpoints.push((name.to_string(), position));
+        info!("Created checkpoint {} at message {}", name, position);
+        position
+    }
+
+    /// Truncates the conversation back to the checkpoint `name`. Checkpoints created
+    /// after it that no longer fit in the conversation are dropped.
+    pub fn restore_to_checkpoint(&mut self, name: &str) -> Result<()> {
+        let position = self
+            .checkpoints
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, position)| *position)
+            .ok_or_else(|| anyhow!("No checkpoint named {}", name))?;
+
+        if position <= self.history.len() {
+            self.history.truncate(position);
+            self.history_access.truncate(position);
+            self.current.clear();
+        } else {
+            self.current.truncate(position - self.history.len());
+        }
+        self.checkpoints
+            .retain(|(_, checkpoint_position)| *checkpoint_position <= position);
+        info!(
+            "Restored conversation to checkpoint {} at message {}",
+            name, position
+        );
+        Ok(())
+    }
+
+    /// Keeps checkpoints marking the same place in the conversation after the history
+    /// messages in `start..start + removed` are replaced by `inserted` new ones.
+    /// Checkpoints inside the replaced range no longer mark a surviving position and
+    /// are dropped.
+    fn splice_checkpoints(&mut self, start: usize, removed: usize, inserted: usize) {
+        let end = start + removed;
+        self.checkpoints
+            .retain(|(_, position)| *position <= start || *position >= end);
+        for (_, position) in &mut self.checkpoints {
+            if *position > start && *position >= end {
+                *position = *position - removed + inserted;
+            }
+        }
+    }
+
+    pub fn list_checkpoints(&self) -> Vec<(&str, usize)> {
+        self.checkpoints
+            .iter()
+            .map(|(name, position)| (name.as_str(), *position))
+            .collect()
+    }
+
     pub fn clear_current(&mut self) {
         info!("Clearing current conversation");
         self.current.clear();
@@ -52,33 +654,205 @@ impl ConversationManager {
 
     pub fn get_combined_conversation(&self) -> Vec<Message> {
         trace!("Getting combined conversation");
-        let mut combined = self.history.clone().into_iter().collect::<Vec<Message>>();
+        self.combine_from(0)
+    }
+
+    /// Like `get_combined_conversation`, but drops the oldest history messages until the
+    /// estimated token count fits in `budget_tokens`. The current exchange is never trimmed.
+    /// This builds the conversation sent to the model, so every history message included
+    /// is marked as accessed.
+    pub fn get_combined_conversation_within_budget(
+        &mut self,
+        budget_tokens: usize,
+    ) -> Vec<Message> {
+        let mut skip = 0;
+        loop {
+            let combined = self.combine_from(skip);
+            if estimate_tokens(&combined) <= budget_tokens || skip >= self.history.len() {
+                if skip > 0 {
+                    info!(
+                        "Trimmed {} history messages to fit a budget of {} tokens",
+                        skip, budget_tokens
+                    );
+                }
+                for index in skip..self.history.len() {
+                    self.access_message(index);
+                }
+                self.trimmed_history = skip;
+                return combined;
+            }
+            // Keep the trimmed history starting at a user prompt rather than an orphaned
+            // assistant reply or tool result.
+            skip = self.next_turn_start(skip + 1);
+        }
+    }
+
+    /// Builds the `messages` value for an Anthropic request: the combined conversation
+    /// trimmed to `budget_tokens`, with role alignment fixed, serialized to JSON. Takes
+    /// `&mut self` because trimming marks the included history as accessed.
+    pub fn to_anthropic_messages_value(&mut self, budget_tokens: usize) -> Result<Value> {
+        let combined = self.get_combined_conversation_within_budget(budget_tokens);
+        serde_json::to_value(&combined).map_err(|e| anyhow!("Failed to serialize messages: {}", e))
+    }
+
+    fn combine_from(&self, history_skip: usize) -> Vec<Message> {
+        let mut combined = self
+            .history
+            .iter()
+            .skip(history_skip)
+            .cloned()
+            .collect::<Vec<Message>>();
         combined.extend(self.current.clone());
+
+        if let Err(violations) = validate_role_alignment(&combined) {
+            warn!(
+                "Role alignment violations at message indices: {:?}",
+                violations
+            );
+            for index in violations.into_iter().rev() {
+                if combined[index].role == "assistant" {
+                    combined.insert(index, Message::text("user", ROLE_ALIGNMENT_PLACEHOLDER));
+                }
+            }
+        }
+
         info!("Combined conversation size: {}", combined.len());
         combined
     }
 
+    /// Removes messages from history that exactly repeat the message before them, such
+    /// as a tool result submitted twice.
+    pub fn deduplicate_history(&mut self) {
+        let original_len = self.history.len();
+        let mut kept: VecDeque<(Message, u64)> = VecDeque::with_capacity(original_len);
+        let mut removed_indices = Vec::new();
+        for entry in self.history.drain(..).zip(self.history_access.drain(..)) {
+            match kept.back_mut() {
+                Some((previous, accessed)) if is_repeat_of(previous, &entry.0) => {
+                    *accessed = (*accessed).max(entry.1);
+                    removed_indices.push(kept.len());
+                }
+                _ => kept.push_back(entry),
+            }
+        }
+        (self.history, self.history_access) = kept.into_iter().unzip();
+        for index in removed_indices {
+            self.splice_checkpoints(index, 1, 0);
+        }
+        debug!(
+            "Removed {} duplicate messages from history",
+            original_len - self.history.len()
+        );
+    }
+
     pub fn commit_current_to_history(&mut self) {
         info!("Committing current conversation to history");
-        for message in self.current.clone().drain(..) {
+        for message in std::mem::take(&mut self.current) {
+            if let Some(store) = &self.store {
+                if let Err(e) = store.save_message(&self.session_id.to_string(), &message) {
+                    warn!("Failed to save message to conversation store: {}", e);
+                }
+            }
             self.add_to_history(message);
         }
+        self.deduplicate_history();
         info!("Current conversation cleared after commit");
     }
 
     pub fn save_chat(&self) -> std::io::Result<String> {
         info!("Saving chat to file");
-        // Generate filename
-        let now = Local::now();
-        let filename = format!("Chat_{}.md", now.format("%H%M"));
+        let filename = self.chat_filename();
         info!("Generated filename: {}", filename);
+        self.save_chat_to(&filename, &self.chat_template)?;
+        Ok(filename)
+    }
+
+    /// Saves the conversation as a self-contained HTML page named like `save_chat`'s
+    /// Markdown file, and returns the filename.
+    pub fn save_chat_html(&self) -> std::io::Result<String> {
+        let filename = Path::new(&self.chat_filename())
+            .with_extension("html")
+            .to_string_lossy()
+            .into_owned();
+        self.save_chat_html_to(&filename, &self.chat_template)?;
+        Ok(filename)
+    }
+
+    /// Default file for `save_session`: `Session_<name>_<HHMM>.json` for named sessions,
+    /// otherwise `Session_<timestamp>.json`.
+    pub fn session_filename(&self) -> String {
+        let now = Local::now();
+        match self.filename_safe_name() {
+            Some(name) => format!("Session_{}_{}.json", name, now.format("%H%M")),
+            None => format!("Session_{}.json", now.format("%Y%m%d_%H%M%S")),
+        }
+    }
+
+    /// Writes the history and current exchange to `path` as JSON, to be continued later
+    /// with `load_session`. Message token counts are not saved.
+    pub fn save_session(&self, path: &str) -> Result<()> {
+        let file = File::create(path)
+            .map_err(|e| anyhow!("Failed to create session file {}: {}", path, e))?;
+        let session = SavedSession {
+            max_history_size: self.max_history_size,
+            history: self.history.iter().cloned().collect(),
+            current: self.current.clone(),
+            branch_id: Some(self.branch_id),
+            parent_id: self.parent_id,
+            session_name: self.session_name.clone(),
+            created_at: self.created_at,
+        };
+        serde_json::to_writer_pretty(file, &session)?;
+        info!(
+            "Saved session with {} messages to {}",
+            self.message_count(),
+            path
+        );
+        Ok(())
+    }
+
+    /// Reads a conversation written by `save_session`.
+    pub fn load_session(path: &str) -> Result<Self> {
+        let file =
+            File::open(path).map_err(|e| anyhow!("Failed to open session file {}: {}", path, e))?;
+        let session: SavedSession = serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| anyhow!("Invalid session file {}: {}", path, e))?;
 
+        let mut manager = Self::new(session.max_history_size);
+        manager.history_access = session.history.iter().map(|_| 0).collect();
+        manager.history = session.history.into();
+        manager.current = session.current;
+        if let Some(branch_id) = session.branch_id {
+            manager.branch_id = branch_id;
+        }
+        manager.parent_id = session.parent_id;
+        manager.session_name = session.session_name;
+        manager.created_at = session.created_at;
+        info!(
+            "Loaded session with {} messages from {}",
+            manager.message_count(),
+            path
+        );
+        Ok(manager)
+    }
+
+    /// Writes the conversation to `path` as Markdown laid out by `template`.
+    pub fn save_chat_to(&self, path: &str, template: &ChatSaveTemplate) -> std::io::Result<()> {
         // Format conversation history
-        let mut formatted_chat = String::from("# Claude-3-Sonnet Engineer Chat Log\n\n");
+        let mut formatted_chat = format!("# {}\n\n", template.title);
+        if template.include_timestamps {
+            formatted_chat.push_str(&format!(
+                "Saved: {}\n\n",
+                Local::now().format("%Y-%m-%d %H:%M:%S")
+            ));
+        }
+        if let Some(persona) = &self.persona {
+            formatted_chat.push_str(&format!("Persona: {}\n\n", persona));
+        }
         for message in self.get_combined_conversation() {
             match message.role.as_str() {
                 "user" => {
-                    formatted_chat.push_str("## User\n\n");
+                    formatted_chat.push_str(&format!("## {}\n\n", template.user_heading));
                     match message.content {
                         MessageContent::Text(text) => {
                             formatted_chat.push_str(&format!("{}\n\n", text))
@@ -86,8 +860,8 @@ impl ConversationManager {
                         MessageContent::ToolUseUser(result) => {
                             for tool_use in result {
                                 formatted_chat.push_str(&format!(
-                                    "### Tool Use: {}\n\n```json\n{}\n```\n\n",
-                                    tool_use.tool_type, tool_use.content
+                                    "### {}: {}\n\n```json\n{}\n```\n\n",
+                                    template.tool_heading, tool_use.tool_type, tool_use.content
                                 ))
                             }
                         }
@@ -95,7 +869,7 @@ impl ConversationManager {
                     }
                 }
                 "assistant" => {
-                    formatted_chat.push_str("## Claude\n\n");
+                    formatted_chat.push_str(&format!("## {}\n\n", template.assistant_heading));
                     match message.content {
                         MessageContent::Text(text) => {
                             formatted_chat.push_str(&format!("{}\n\n", text))
@@ -103,8 +877,8 @@ impl ConversationManager {
                         MessageContent::ToolUseAssistant(assistant_tool_uses) => {
                             for tool_use in assistant_tool_uses {
                                 formatted_chat.push_str(&format!(
-                                    "### Tool Use: {}\n\n```json\n{}\n```\n\n",
-                                    tool_use.name, tool_use.input
+                                    "### {}: {}\n\n```json\n{}\n```\n\n",
+                                    template.tool_heading, tool_use.name, tool_use.input
                                 ))
                             }
                         }
@@ -117,12 +891,79 @@ impl ConversationManager {
             }
         }
 
-        // Save to file
-        let mut file = File::create(&filename)?;
-        file.write_all(formatted_chat.as_bytes())?;
-        info!("Chat saved to file: {}", filename);
+        write_chat_file(path, &formatted_chat)
+    }
 
-        Ok(filename)
+    /// Writes the conversation to `path` as HTML, using `template`'s title and headings.
+    pub fn save_chat_html_to(
+        &self,
+        path: &str,
+        template: &ChatSaveTemplate,
+    ) -> std::io::Result<()> {
+        let title = escape_html(&template.title);
+        let mut body = format!("<h1>{}</h1>\n", title);
+        if template.include_timestamps {
+            body.push_str(&format!(
+                "<p>Saved: {}</p>\n",
+                Local::now().format("%Y-%m-%d %H:%M:%S")
+            ));
+        }
+        if let Some(persona) = &self.persona {
+            body.push_str(&format!("<p>Persona: {}</p>\n", escape_html(persona)));
+        }
+        for message in self.get_combined_conversation() {
+            let heading = match message.role.as_str() {
+                "user" => &template.user_heading,
+                "assistant" => &template.assistant_heading,
+                _ => {
+                    warn!("Unknown message role: {}", message.role);
+                    continue;
+                }
+            };
+            body.push_str(&format!("<h2>{}</h2>\n", escape_html(heading)));
+            match message.content {
+                MessageContent::Text(text) => body.push_str(&text_to_html(&text)),
+                MessageContent::ToolUseAssistant(tool_uses) => {
+                    for tool_use in tool_uses {
+                        body.push_str(&tool_block_html(
+                            &format!("{}: {}", template.tool_heading, tool_use.name),
+                            &tool_use.input.to_string(),
+                        ));
+                    }
+                }
+                MessageContent::ToolUseUser(results) => {
+                    for result in results {
+                        body.push_str(&tool_block_html(
+                            &format!("{}: {}", template.tool_heading, result.tool_type),
+                            &result.content,
+                        ));
+                    }
+                }
+            }
+        }
+
+        let html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+            title, CHAT_HTML_STYLE, body
+        );
+        write_chat_file(path, &html)
+    }
+
+    /// Converts the conversation to OpenAI's chat format. Tool calls and results are
+    /// flattened into plain-text `content` strings.
+    pub fn to_openai_format(&self) -> Value {
+        Value::Array(
+            self.history
+                .iter()
+                .chain(self.current.iter())
+                .map(|message| {
+                    json!({
+                        "role": message.role,
+                        "content": message.content.to_plain_text(),
+                    })
+                })
+                .collect(),
+        )
     }
 }
 
@@ -144,22 +985,10 @@ mod tests {
 #[test]
 fn test_add_to_history() {
     let mut cm = ConversationManager::new(3);
-    let message1 = Message {
-        role: "user".to_string(),
-        content: MessageContent::Text("Hello".to_string()),
-    };
-    let message2 = Message {
-        role: "assistant".to_string(),
-        content: MessageContent::Text("Hi there".to_string()),
-    };
-    let message3 = Message {
-        role: "user".to_string(),
-        content: MessageContent::Text("How are you?".to_string()),
-    };
-    let message4 = Message {
-        role: "assistant".to_string(),
-        content: MessageContent::Text("I'm doing well, thanks!".to_string()),
-    };
+    let message1 = Message::text("user", "Hello");
+    let message2 = Message::text("assistant", "Hi there");
+    let message3 = Message::text("user", "How are you?");
+    let message4 = Message::text("assistant", "I'm doing well, thanks!");
 
     cm.add_to_history(message1.clone());
     cm.add_to_history(message2.clone());
@@ -178,10 +1007,7 @@ fn test_add_to_history() {
 #[test]
 fn test_add_to_current() {
     let mut cm = ConversationManager::new(5);
-    let message = Message {
-        role: "user".to_string(),
-        content: MessageContent::Text("Hello".to_string()),
-    };
+    let message = Message::text("user", "Hello");
     cm.add_to_current(message.clone());
     assert_eq!(cm.current.len(), 1);
     assert!(matches!(cm.current[0].content, MessageContent::Text(ref s) if s == "Hello"));
@@ -190,10 +1016,7 @@ fn test_add_to_current() {
 #[test]
 fn test_clear_current() {
     let mut cm = ConversationManager::new(5);
-    let message = Message {
-        role: "user".to_string(),
-        content: MessageContent::Text("Hello".to_string()),
-    };
+    let message = Message::text("user", "Hello");
     cm.add_to_current(message);
     assert_eq!(cm.current.len(), 1);
     cm.clear_current();
@@ -203,14 +1026,8 @@ fn test_clear_current() {
 #[test]
 fn test_get_combined_conversation() {
     let mut cm = ConversationManager::new(5);
-    let history_message = Message {
-        role: "user".to_string(),
-        content: MessageContent::Text("Past message".to_string()),
-    };
-    let current_message = Message {
-        role: "assistant".to_string(),
-        content: MessageContent::Text("Current message".to_string()),
-    };
+    let history_message = Message::text("user", "Past message");
+    let current_message = Message::text("assistant", "Current message");
     cm.add_to_history(history_message.clone());
     cm.add_to_current(current_message.clone());
 
@@ -224,14 +1041,8 @@ fn test_get_combined_conversation() {
 fn test_commit_current_to_history() {
     let mut cm = ConversationManager::new(5);
     let mut cm = ConversationManager::new(5);
-    let message1 = Message {
-        role: "user".to_string(),
-        content: MessageContent::Text("Hello".to_string()),
-    };
-    let message2 = Message {
-        role: "assistant".to_string(),
-        content: MessageContent::Text("Hi there".to_string()),
-    };
+    let message1 = Message::text("user", "Hello");
+    let message2 = Message::text("assistant", "Hi there");
     cm.add_to_current(message1.clone());
     cm.add_to_current(message2.clone());
     assert_eq!(cm.current.len(), 2);
@@ -247,20 +1058,637 @@ fn test_commit_current_to_history() {
 #[test]
 fn test_save_chat() {
     let mut cm = ConversationManager::new(5);
-    cm.add_to_current(Message {
-        role: "user".to_string(),
-        content: MessageContent::Text("Hello, Claude!".to_string()),
-    });
-    cm.add_to_current(Message {
-        role: "assistant".to_string(),
-        content: MessageContent::Text("Hello! How can I assist you today?".to_string()),
-    });
+    cm.add_to_current(Message::text("user", "Hello, Claude!"));
+    cm.add_to_current(Message::text(
+        "assistant",
+        "Hello! How can I assist you today?",
+    ));
 
     let result = cm.save_chat();
     assert!(result.is_ok());
     let filename = result.unwrap();
     assert!(filename.starts_with("Chat_") && filename.ends_with(".md"));
+    let _ = fs::remove_file(&filename);
 
     // You might want to add more assertions here to check the content of the file,
     // but that would require reading the file back, which is beyond the scope of this test.
 }
+
+#[test]
+fn test_save_chat_with_nested_filename_format() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut cm = ConversationManager::new(5);
+    cm.set_chat_filename_format(&format!(
+        "{}/logs/{{date}}/Chat_{{time}}.md",
+        temp_dir.path().display()
+    ));
+    cm.add_to_current(Message::text("user", "Hello, Claude!"));
+
+    let filename = cm.save_chat().unwrap();
+    assert!(!filename.contains('{'));
+    assert!(Path::new(&filename).exists());
+}
+
+#[test]
+fn test_validate_role_alignment() {
+    let user = Message::text("user", "Hello");
+    let assistant = Message::text("assistant", "Hi there");
+    assert!(validate_role_alignment(&[user.clone(), assistant.clone()]).is_ok());
+    assert_eq!(
+        validate_role_alignment(&[user.clone(), assistant.clone(), assistant.clone(), user]),
+        Err(vec![2])
+    );
+}
+
+#[test]
+fn test_get_combined_conversation_inserts_placeholder_between_assistant_messages() {
+    let mut cm = ConversationManager::new(5);
+    cm.add_to_history(Message::text("user", "Hello"));
+    cm.add_to_history(Message::text("assistant", "Hi there"));
+    cm.add_to_current(Message::text("assistant", "Anything else?"));
+
+    let combined = cm.get_combined_conversation();
+    assert_eq!(combined.len(), 4);
+    assert_eq!(combined[2].role, "user");
+    assert!(
+        matches!(combined[2].content, MessageContent::Text(ref s) if s == ROLE_ALIGNMENT_PLACEHOLDER)
+    );
+    assert!(validate_role_alignment(&combined).is_ok());
+}
+
+#[test]
+fn test_get_combined_conversation_within_budget_trims_oldest_history() {
+    let mut cm = ConversationManager::new(10);
+    for i in 0..3 {
+        cm.add_to_history(Message::text(
+            "user",
+            format!("Question {} {}", i, "x".repeat(400)),
+        ));
+        cm.add_to_history(Message::text(
+            "assistant",
+            format!("Answer {} {}", i, "y".repeat(400)),
+        ));
+    }
+    cm.add_to_current(Message::text("user", "Current question"));
+
+    assert_eq!(cm.get_combined_conversation_within_budget(10_000).len(), 7);
+
+    let trimmed = cm.get_combined_conversation_within_budget(250);
+    assert_eq!(trimmed.len(), 3);
+    assert!(
+        matches!(trimmed[0].content, MessageContent::Text(ref s) if s.starts_with("Question 2"))
+    );
+    assert!(matches!(trimmed[2].content, MessageContent::Text(ref s) if s == "Current question"));
+
+    // The current exchange is kept even when it alone exceeds the budget.
+    assert_eq!(cm.get_combined_conversation_within_budget(1).len(), 1);
+}
+
+#[test]
+fn test_to_openai_format() {
+    let mut cm = ConversationManager::new(5);
+    cm.add_to_history(Message::text("user", "Read main.rs"));
+    cm.add_to_current(Message::new(
+        "assistant",
+        MessageContent::ToolUseAssistant(vec![ToolUseAssistant {
+            tool_type: "tool_use".to_string(),
+            id: "toolu_1".to_string(),
+            name: "read_file".to_string(),
+            input: json!({ "path": "main.rs" }),
+        }]),
+    ));
+    cm.add_to_current(Message::new(
+        "user",
+        MessageContent::ToolUseUser(vec![ToolUseUser {
+            tool_type: "tool_result".to_string(),
+            tool_use_id: "toolu_1".to_string(),
+            content: "fn main() {}".to_string(),
+        }]),
+    ));
+
+    let openai = cm.to_openai_format();
+    assert_eq!(openai.as_array().unwrap().len(), 3);
+    assert_eq!(
+        openai[0],
+        json!({ "role": "user", "content": "Read main.rs" })
+    );
+    assert_eq!(openai[1]["role"], "assistant");
+    assert!(openai[1]["content"]
+        .as_str()
+        .unwrap()
+        .contains("[tool_use read_file]"));
+    assert_eq!(openai[2]["content"], "[tool_result] fn main() {}");
+}
+
+#[test]
+fn test_prune_tool_results() {
+    let mut cm = ConversationManager::new(10);
+    for i in 0..3 {
+        cm.add_to_history(Message::new(
+            "user",
+            MessageContent::ToolUseUser(vec![ToolUseUser {
+                tool_type: "tool_result".to_string(),
+                tool_use_id: format!("toolu_{}", i),
+                content: format!("file contents {}", i),
+            }]),
+        ));
+        cm.add_to_history(Message::text("assistant", format!("Reply {}", i)));
+    }
+
+    cm.prune_tool_results(1);
+
+    let combined = cm.get_combined_conversation();
+    assert!(
+        matches!(combined[0].content, MessageContent::Text(ref s) if s == TOOL_RESULT_PLACEHOLDER)
+    );
+    assert!(
+        matches!(combined[2].content, MessageContent::Text(ref s) if s == TOOL_RESULT_PLACEHOLDER)
+    );
+    assert!(matches!(
+        combined[4].content,
+        MessageContent::ToolUseUser(_)
+    ));
+    assert!(matches!(combined[5].content, MessageContent::Text(ref s) if s == "Reply 2"));
+}
+
+#[cfg(test)]
+fn tool_use_exchange() -> (Message, Message) {
+    let assistant = Message::new(
+        "assistant",
+        MessageContent::ToolUseAssistant(vec![ToolUseAssistant {
+            tool_type: "tool_use".to_string(),
+            id: "toolu_01".to_string(),
+            name: "read_file".to_string(),
+            input: json!({ "path": "src/main.rs" }),
+        }]),
+    );
+    let user = Message::new(
+        "user",
+        MessageContent::ToolUseUser(vec![ToolUseUser {
+            tool_type: "tool_result".to_string(),
+            tool_use_id: "toolu_01".to_string(),
+            content: "fn main() {}".to_string(),
+        }]),
+    );
+    (assistant, user)
+}
+
+#[test]
+fn test_tool_use_messages_survive_commit_to_history() {
+    let mut cm = ConversationManager::new(5);
+    let (assistant, user) = tool_use_exchange();
+    cm.add_to_current(Message::text("user", "Read main.rs"));
+    cm.add_to_current(assistant);
+    cm.add_to_current(user);
+    cm.commit_current_to_history();
+    cm.clear_current();
+
+    let combined = cm.get_combined_conversation();
+    assert_eq!(combined.len(), 3);
+    match &combined[1].content {
+        MessageContent::ToolUseAssistant(tool_uses) => {
+            assert_eq!(tool_uses[0].name, "read_file");
+            assert_eq!(tool_uses[0].input["path"], "src/main.rs");
+        }
+        other => panic!("expected ToolUseAssistant, got {:?}", other),
+    }
+    match &combined[2].content {
+        MessageContent::ToolUseUser(results) => {
+            assert_eq!(results[0].tool_use_id, "toolu_01");
+            assert_eq!(results[0].content, "fn main() {}");
+        }
+        other => panic!("expected ToolUseUser, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_tool_use_messages_serde_roundtrip() {
+    let (assistant, user) = tool_use_exchange();
+
+    let assistant_json = serde_json::to_value(&assistant).unwrap();
+    assert_eq!(assistant_json["content"][0]["type"], "tool_use");
+    let assistant: Message = serde_json::from_value(assistant_json).unwrap();
+    assert!(matches!(
+        assistant.content,
+        MessageContent::ToolUseAssistant(_)
+    ));
+
+    let user_json = serde_json::to_value(&user).unwrap();
+    assert_eq!(user_json["content"][0]["type"], "tool_result");
+    let user: Message = serde_json::from_value(user_json).unwrap();
+    assert!(matches!(user.content, MessageContent::ToolUseUser(_)));
+}
+
+#[test]
+fn test_save_chat_includes_tool_use_sections() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut cm = ConversationManager::new(5);
+    cm.set_chat_filename_format(&format!(
+        "{}/Chat_{{timestamp}}.md",
+        temp_dir.path().display()
+    ));
+    let (assistant, user) = tool_use_exchange();
+    cm.add_to_current(Message::text("user", "Read main.rs"));
+    cm.add_to_current(assistant);
+    cm.add_to_current(user);
+
+    let filename = cm.save_chat().unwrap();
+    let saved = fs::read_to_string(filename).unwrap();
+    assert!(saved.contains("### Tool Use: read_file"));
+    assert!(saved.contains("src/main.rs"));
+    assert!(saved.contains("### Tool Use: tool_result"));
+    assert!(saved.contains("fn main() {}"));
+}
+
+#[test]
+fn test_restore_to_checkpoint() {
+    let mut cm = ConversationManager::new(10);
+    let message = |role: &str, text: &str| Message::text(role, text);
+    cm.add_to_history(message("user", "Plan the feature"));
+    cm.add_to_history(message("assistant", "Here is the plan"));
+    assert_eq!(cm.create_checkpoint("planning"), 2);
+
+    cm.add_to_history(message("user", "Implement it"));
+    cm.add_to_current(message("assistant", "Done"));
+    assert_eq!(cm.create_checkpoint("implementation"), 4);
+    assert_eq!(
+        cm.list_checkpoints(),
+        vec![("planning", 2), ("implementation", 4)]
+    );
+
+    cm.add_to_current(message("user", "Now test it"));
+    cm.restore_to_checkpoint("implementation").unwrap();
+    assert_eq!((cm.history.len(), cm.current.len()), (3, 1));
+
+    cm.restore_to_checkpoint("planning").unwrap();
+    assert_eq!((cm.history.len(), cm.current.len()), (2, 0));
+    assert_eq!(cm.list_checkpoints(), vec![("planning", 2)]);
+    assert!(cm.restore_to_checkpoint("implementation").is_err());
+}
+
+#[test]
+fn test_checkpoints_follow_history_eviction() {
+    let mut cm = ConversationManager::new(4);
+    let message = |role: &str, text: &str| Message::text(role, text);
+    cm.create_checkpoint("start");
+    cm.add_to_history(message("user", "Plan the feature"));
+    cm.add_to_history(message("assistant", "Here is the plan"));
+    assert_eq!(cm.create_checkpoint("planned"), 2);
+    cm.add_to_history(message("user", "Implement it"));
+    cm.add_to_history(message("assistant", "Done"));
+    assert_eq!(cm.create_checkpoint("implemented"), 4);
+
+    cm.add_to_history(message("user", "Now test it"));
+    assert_eq!(
+        cm.list_checkpoints(),
+        vec![("start", 0), ("planned", 1), ("implemented", 3)]
+    );
+
+    cm.replace_oldest_with_summary(1, "Planned the feature");
+    assert_eq!(
+        cm.list_checkpoints(),
+        vec![("start", 0), ("planned", 2), ("implemented", 4)]
+    );
+
+    cm.restore_to_checkpoint("planned").unwrap();
+    assert_eq!(cm.history.len(), 2);
+    assert!(
+        matches!(cm.history[0].content, MessageContent::Text(ref s) if s.starts_with(SUMMARY_PREFIX))
+    );
+}
+
+#[test]
+fn test_least_recently_accessed_eviction() {
+    let mut cm = ConversationManager::new(3);
+    cm.set_eviction_policy(EvictionPolicy::LeastRecentlyAccessed);
+    let message = |text: &str| Message::text("user", text);
+    cm.add_to_history(message("Architecture decision"));
+    cm.add_to_history(message("Small talk"));
+    cm.add_to_history(message("More small talk"));
+
+    cm.access_message(0);
+    cm.add_to_history(message("New request"));
+
+    let texts = cm
+        .history
+        .iter()
+        .map(|m| m.content.to_plain_text())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        texts,
+        vec!["Architecture decision", "More small talk", "New request"]
+    );
+}
+
+#[test]
+fn test_save_chat_to_with_custom_template() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join("chat.md");
+    let mut cm = ConversationManager::new(5);
+    cm.add_to_current(Message::text("user", "Hello"));
+    cm.add_to_current(Message::text("assistant", "Hi"));
+    let template = ChatSaveTemplate {
+        title: "Pairing Session".to_string(),
+        user_heading: "Me".to_string(),
+        assistant_heading: "Assistant".to_string(),
+        include_timestamps: true,
+        ..ChatSaveTemplate::default()
+    };
+
+    cm.save_chat_to(path.to_str().unwrap(), &template).unwrap();
+    let saved = fs::read_to_string(path).unwrap();
+    assert!(saved.starts_with("# Pairing Session\n\nSaved: "));
+    assert!(saved.contains("## Me\n\nHello"));
+    assert!(saved.contains("## Assistant\n\nHi"));
+}
+
+#[test]
+fn test_deduplicate_history_removes_repeated_tool_results() {
+    let mut cm = ConversationManager::new(10);
+    let (assistant, user) = tool_use_exchange();
+    cm.add_to_history(assistant);
+    cm.add_to_history(user.clone());
+    cm.add_to_history(user.clone());
+    cm.add_to_history(user);
+
+    cm.deduplicate_history();
+
+    assert_eq!(cm.history.len(), 2);
+    assert_eq!(cm.history_access.len(), 2);
+    assert!(matches!(
+        cm.history[1].content,
+        MessageContent::ToolUseUser(_)
+    ));
+}
+
+#[cfg(test)]
+#[derive(Debug, Default)]
+struct RecordingStore {
+    saved: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+}
+
+#[cfg(test)]
+impl ConversationStore for RecordingStore {
+    fn save_message(&self, _session_id: &str, message: &Message) -> Result<()> {
+        self.saved
+            .lock()
+            .unwrap()
+            .push(message.content.to_plain_text());
+        Ok(())
+    }
+
+    fn load_session(&self, _session_id: &str) -> Result<Vec<Message>> {
+        Ok(Vec::new())
+    }
+
+    fn list_sessions(&self) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+}
+
+#[test]
+fn test_store_only_receives_committed_messages() {
+    let store = RecordingStore::default();
+    let saved = store.saved.clone();
+    let mut cm = ConversationManager::new(10);
+    cm.set_store(Box::new(store));
+
+    cm.add_to_current(Message::text("user", "Hello"));
+    cm.clear_current();
+    cm.add_to_current(Message::text("user", "Hello"));
+    assert!(saved.lock().unwrap().is_empty());
+
+    cm.add_to_current(Message::text("assistant", "Hi there"));
+    cm.commit_current_to_history();
+    assert_eq!(*saved.lock().unwrap(), vec!["Hello", "Hi there"]);
+}
+
+#[test]
+fn test_merge() {
+    let message = |role: &str, text: &str| Message::text(role, text);
+    let mut frontend = ConversationManager::new(3);
+    frontend.add_to_history(message("user", "Fix the login form"));
+    frontend.add_to_history(message("assistant", "Fixed"));
+    frontend.add_to_current(message("user", "Now style it"));
+    let mut backend = ConversationManager::new(10);
+    backend.add_to_history(message("user", "Add a login endpoint"));
+    backend.add_to_history(message("assistant", "Added"));
+
+    let merged = frontend.merge(backend);
+
+    let texts = merged
+        .history
+        .iter()
+        .map(|m| m.content.to_plain_text())
+        .collect::<Vec<_>>();
+    assert_eq!(texts, vec!["Fixed", "Add a login endpoint", "Added"]);
+    assert_eq!(merged.current.len(), 1);
+}
+
+#[test]
+fn test_to_anthropic_messages_value_serializes_combined_conversation() {
+    let mut cm = ConversationManager::new(5);
+    cm.add_to_current(Message::text("user", "Hello"));
+
+    let messages = cm.to_anthropic_messages_value(10_000).unwrap();
+    assert_eq!(messages[0]["role"], "user");
+    assert_eq!(messages.as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn test_record_input_tokens_back_fills_new_messages() {
+    let mut cm = ConversationManager::new(10);
+    let message = |role: &str, text: &str| Message::text(role, text);
+    cm.add_to_history(message("user", "Hello"));
+    cm.add_to_history(message("assistant", "Hi there"));
+    cm.add_to_current(message("user", "How are you?"));
+
+    cm.record_input_tokens(301);
+    assert_eq!(cm.total_token_count(), 301);
+
+    cm.commit_current_to_history();
+    cm.clear_current();
+    cm.add_to_current(message("user", "Tell me more"));
+    cm.record_input_tokens(350);
+    assert_eq!(cm.total_token_count(), 350);
+    assert_eq!(
+        cm.get_combined_conversation().last().unwrap().token_count,
+        49
+    );
+}
+
+#[test]
+fn test_record_input_tokens_skips_trimmed_history() {
+    let mut cm = ConversationManager::new(10);
+    cm.add_to_history(Message::text("user", "a".repeat(2000)));
+    cm.add_to_history(Message::text("assistant", "b".repeat(2000)));
+    cm.add_to_history(Message::text("user", "Short question"));
+    cm.add_to_history(Message::text("assistant", "Short answer"));
+    cm.add_to_current(Message::text("user", "Another question"));
+
+    let sent = cm.get_combined_conversation_within_budget(200);
+    assert_eq!(sent.len(), 3);
+    cm.record_input_tokens(90);
+
+    let combined = cm.get_combined_conversation();
+    assert_eq!(combined[0].token_count, 0);
+    assert_eq!(combined[1].token_count, 0);
+    assert_eq!(cm.total_token_count(), 90);
+}
+
+#[test]
+fn test_replace_oldest_with_summary() {
+    let mut cm = ConversationManager::new(10);
+    for i in 0..4 {
+        cm.add_to_history(Message {
+            role: if i % 2 == 0 { "user" } else { "assistant" }.to_string(),
+            content: MessageContent::Text(format!("Message {}", i)),
+            token_count: 100,
+        });
+    }
+
+    assert_eq!(cm.oldest_history(2).len(), 2);
+    cm.replace_oldest_with_summary(1, "We said hello.");
+    let conversation = cm.get_combined_conversation();
+    assert_eq!(conversation.len(), 4);
+    assert_eq!(conversation[0].role, "user");
+    assert_eq!(
+        conversation[0].content.to_plain_text(),
+        "[SUMMARY] We said hello."
+    );
+    assert_eq!(
+        conversation[1].content.to_plain_text(),
+        SUMMARY_ACKNOWLEDGEMENT
+    );
+    assert_eq!(conversation[2].content.to_plain_text(), "Message 2");
+    assert_eq!(cm.total_token_count(), 200);
+    assert!(validate_role_alignment(&conversation).is_ok());
+}
+
+#[test]
+fn test_replace_oldest_with_summary_keeps_tool_use_with_its_result() {
+    let mut cm = ConversationManager::new(10);
+    let (assistant, user) = tool_use_exchange();
+    cm.add_to_history(Message::text("user", "Read main.rs"));
+    cm.add_to_history(assistant);
+    cm.add_to_history(user);
+    cm.add_to_history(Message::text("assistant", "It has an empty main."));
+    cm.add_to_history(Message::text("user", "Add a greeting"));
+    cm.add_to_history(Message::text("assistant", "Done."));
+
+    assert_eq!(cm.next_turn_start(2), 4);
+    cm.replace_oldest_with_summary(2, "Claude read main.rs.");
+    let conversation = cm.get_combined_conversation();
+    assert_eq!(conversation.len(), 4);
+    assert!(conversation
+        .iter()
+        .all(|message| !matches!(message.content, MessageContent::ToolUseUser(_))));
+    assert_eq!(conversation[2].content.to_plain_text(), "Add a greeting");
+    assert!(validate_role_alignment(&conversation).is_ok());
+}
+
+#[test]
+fn test_save_and_load_session() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join("session.json");
+    let path = path.to_str().unwrap();
+
+    let mut cm = ConversationManager::new(7);
+    cm.add_to_history(Message::text("user", "Hello"));
+    cm.add_to_current(Message::text("assistant", "Hi there"));
+    cm.save_session(path).unwrap();
+
+    let loaded = ConversationManager::load_session(path).unwrap();
+    assert_eq!(loaded.max_history_size, 7);
+    assert_eq!(loaded.history.len(), 1);
+    assert_eq!(loaded.current.len(), 1);
+    assert_eq!(
+        loaded.get_combined_conversation()[1]
+            .content
+            .to_plain_text(),
+        "Hi there"
+    );
+    assert_eq!(loaded.branch_id(), cm.branch_id());
+    assert_eq!(loaded.name(), None);
+    assert_eq!(loaded.created_at(), cm.created_at());
+}
+
+#[test]
+fn test_named_session_filenames() {
+    let mut cm = ConversationManager::new(10);
+    assert!(cm.chat_filename().starts_with("Chat_2"));
+    assert!(cm.session_filename().starts_with("Session_2"));
+
+    cm.set_name("my project");
+    assert_eq!(cm.name(), Some("my project"));
+    let filename = cm.chat_filename();
+    assert!(filename.starts_with("Chat_my_project_"), "{}", filename);
+    assert_eq!(filename.len(), "Chat_my_project_1430.md".len());
+    assert!(cm.session_filename().starts_with("Session_my_project_"));
+}
+
+#[test]
+fn test_search_history_and_filter_by_role() {
+    let mut cm = ConversationManager::new(10);
+    cm.add_to_history(Message::text("user", "Fix the Parser"));
+    cm.add_to_history(Message::text("assistant", "Done"));
+    cm.add_to_current(Message::text("user", "Now test the parser (again)"));
+
+    let matches = cm.search_history("parser", false);
+    assert_eq!(
+        matches.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+        vec![0, 2]
+    );
+    assert_eq!(cm.search_history("parser", true).len(), 1);
+    assert_eq!(cm.search_history("^(Fix|Done)$", true)[0].0, 1);
+    assert_eq!(cm.search_history("(again", false)[0].0, 2);
+    assert_eq!(cm.filter_by_role("user").len(), 2);
+}
+
+#[test]
+fn test_save_chat_html_to() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join("chat.html");
+    let path = path.to_str().unwrap();
+
+    let mut cm = ConversationManager::new(10);
+    cm.add_to_current(Message::text(
+        "user",
+        "Fix <this>:\n\n```\nfn main() {}\n```",
+    ));
+    cm.add_to_current(Message::new(
+        "assistant",
+        MessageContent::ToolUseAssistant(vec![ToolUseAssistant {
+            tool_type: "tool_use".to_string(),
+            id: "tool_1".to_string(),
+            name: "read_file".to_string(),
+            input: json!({ "path": "src/main.rs" }),
+        }]),
+    ));
+    cm.save_chat_html_to(path, &ChatSaveTemplate::default())
+        .unwrap();
+
+    let html = fs::read_to_string(path).unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<p>Fix &lt;this&gt;:</p>"));
+    assert!(html.contains("<pre><code class=\"language-rust\">fn main() {}</code></pre>"));
+    assert!(html.contains("<details>\n<summary>Tool Use: read_file</summary>"));
+    assert!(html.contains("{&quot;path&quot;:&quot;src/main.rs&quot;}"));
+}
+
+#[test]
+fn test_branch() {
+    let mut cm = ConversationManager::new(10);
+    cm.add_to_history(Message::text("user", "Hello"));
+    cm.add_to_current(Message::text("assistant", "Hi there"));
+
+    let mut branch = cm.branch();
+    assert_eq!(branch.history.len(), 1);
+    assert!(branch.current.is_empty());
+    assert_eq!(branch.parent_id(), Some(cm.branch_id()));
+    assert_ne!(branch.branch_id(), cm.branch_id());
+    assert_eq!(cm.parent_id(), None);
+
+    branch.clear_history();
+    assert_eq!(cm.message_count(), 2);
+}
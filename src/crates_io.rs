@@ -0,0 +1,238 @@
+use anyhow::{anyhow, Context, Result};
+use flate2::read::GzDecoder;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const CRATES_IO_API_URL: &str = "https://crates.io/api/v1/crates";
+const CRATE_DOWNLOAD_URL: &str = "https://static.crates.io/crates";
+// crates.io rejects requests without a descriptive user agent.
+const USER_AGENT: &str = concat!("claude-engineer-rs/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CrateMetadata {
+    pub name: String,
+    pub latest_version: String,
+    pub description: Option<String>,
+    pub repository: Option<String>,
+    pub documentation: Option<String>,
+    pub license: Option<String>,
+    pub features: HashMap<String, Vec<String>>,
+}
+
+impl CrateMetadata {
+    /// The dependency line to add under `[dependencies]` in `Cargo.toml`.
+    pub fn cargo_toml_snippet(&self) -> String {
+        format!("{} = \"{}\"", self.name, self.latest_version)
+    }
+
+    fn from_api_response(crate_name: &str, response: &Value) -> Result<Self> {
+        let krate = response
+            .get("crate")
+            .ok_or_else(|| anyhow!("Unexpected crates.io response for {}", crate_name))?;
+        let string_field = |value: &Value, key: &str| value[key].as_str().map(String::from);
+
+        let latest_version = string_field(krate, "max_stable_version")
+            .or_else(|| string_field(krate, "max_version"))
+            .ok_or_else(|| anyhow!("No published versions found for {}", crate_name))?;
+
+        let version = response["versions"]
+            .as_array()
+            .and_then(|versions| {
+                versions
+                    .iter()
+                    .find(|v| v["num"].as_str() == Some(latest_version.as_str()))
+            })
+            .cloned()
+            .unwrap_or(Value::Null);
+
+        let features = version["features"]
+            .as_object()
+            .map(|features| {
+                features
+                    .iter()
+                    .map(|(name, enables)| {
+                        let enables = enables
+                            .as_array()
+                            .map(|e| {
+                                e.iter()
+                                    .filter_map(|f| f.as_str().map(String::from))
+                                    .collect()
+                            })
+                            .unwrap_or_default();
+                        (name.clone(), enables)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(CrateMetadata {
+            name: string_field(krate, "name").unwrap_or_else(|| crate_name.to_string()),
+            latest_version,
+            description: string_field(krate, "description"),
+            repository: string_field(krate, "repository"),
+            documentation: string_field(krate, "documentation"),
+            license: string_field(&version, "license"),
+            features,
+        })
+    }
+}
+
+pub async fn fetch_crate_metadata(crate_name: &str) -> Result<CrateMetadata> {
+    let url = format!("{}/{}", CRATES_IO_API_URL, crate_name);
+    let response = reqwest::Client::new()
+        .get(&url)
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach crates.io for {}", crate_name))?;
+
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(anyhow!("Crate not found on crates.io: {}", crate_name));
+    }
+    let body = response.error_for_status()?.text().await?;
+    let json: Value = serde_json::from_str(&body)?;
+    CrateMetadata::from_api_response(crate_name, &json)
+}
+
+/// Reads `file_path` from the published source of `crate_name` at `version`. The
+/// `.crate` tarball is cached in the temp directory, so reading several files from the
+/// same version downloads it once.
+pub async fn fetch_crate_source_file(
+    crate_name: &str,
+    version: &str,
+    file_path: &str,
+) -> Result<String> {
+    let tarball = download_crate_tarball(crate_name, version).await?;
+    read_file_from_tarball(&tarball, file_path).with_context(|| {
+        format!(
+            "Failed to read {} from {} {}",
+            file_path, crate_name, version
+        )
+    })
+}
+
+fn tarball_cache_path(crate_name: &str, version: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join("claude-engineer-rs-crates")
+        .join(format!("{}-{}.crate", crate_name, version))
+}
+
+async fn download_crate_tarball(crate_name: &str, version: &str) -> Result<Vec<u8>> {
+    let is_valid = |s: &str| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+'))
+    };
+    if !is_valid(crate_name) || !is_valid(version) || version.contains("..") {
+        return Err(anyhow!(
+            "Invalid crate name or version: {} {}",
+            crate_name,
+            version
+        ));
+    }
+
+    let cache_path = tarball_cache_path(crate_name, version);
+    if let Ok(tarball) = fs::read(&cache_path) {
+        return Ok(tarball);
+    }
+
+    let url = format!("{}/{}/{}/download", CRATE_DOWNLOAD_URL, crate_name, version);
+    let response = reqwest::Client::new()
+        .get(&url)
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .send()
+        .await
+        .with_context(|| format!("Failed to download {} {}", crate_name, version))?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(anyhow!(
+            "Version {} of {} not found on crates.io",
+            version,
+            crate_name
+        ));
+    }
+    let tarball = response.error_for_status()?.bytes().await?.to_vec();
+
+    if let Some(parent) = cache_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&cache_path, &tarball)?;
+    Ok(tarball)
+}
+
+/// Extracts `file_path`, relative to the crate root, from a gzipped `.crate` tarball
+/// whose entries are all under a single `<name>-<version>/` directory.
+fn read_file_from_tarball(tarball: &[u8], file_path: &str) -> Result<String> {
+    let wanted = Path::new(file_path.trim_start_matches("./"));
+    let mut archive = tar::Archive::new(GzDecoder::new(tarball));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if path.components().skip(1).eq(wanted.components()) {
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            return Ok(content);
+        }
+    }
+    Err(anyhow!("File not found in crate: {}", file_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_metadata_from_api_response() {
+        let response = json!({
+            "crate": {
+                "name": "serde",
+                "max_version": "2.0.0-alpha.1",
+                "max_stable_version": "1.0.204",
+                "description": "A serialization framework",
+                "repository": "https://github.com/serde-rs/serde",
+                "documentation": "https://docs.rs/serde"
+            },
+            "versions": [
+                { "num": "2.0.0-alpha.1", "license": "MIT", "features": {} },
+                {
+                    "num": "1.0.204",
+                    "license": "MIT OR Apache-2.0",
+                    "features": { "derive": ["serde_derive"], "std": [] }
+                }
+            ]
+        });
+
+        let metadata = CrateMetadata::from_api_response("serde", &response).unwrap();
+        assert_eq!(metadata.latest_version, "1.0.204");
+        assert_eq!(metadata.license.as_deref(), Some("MIT OR Apache-2.0"));
+        assert_eq!(metadata.features["derive"], vec!["serde_derive"]);
+        assert_eq!(metadata.cargo_toml_snippet(), "serde = \"1.0.204\"");
+    }
+
+    #[test]
+    fn test_read_file_from_tarball() {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        let source = b"pub fn hello() {}\n";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(source.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "demo-0.1.0/src/lib.rs", &source[..])
+            .unwrap();
+        let tarball = builder.into_inner().unwrap().finish().unwrap();
+
+        assert_eq!(
+            read_file_from_tarball(&tarball, "src/lib.rs").unwrap(),
+            "pub fn hello() {}\n"
+        );
+        assert!(read_file_from_tarball(&tarball, "src/main.rs").is_err());
+    }
+}
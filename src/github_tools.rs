@@ -1,6 +1,37 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use dotenv::dotenv;
-use octocrab::{models::repos::RepoCommit, Octocrab};
+use octocrab::{
+    models::{
+        issues::Issue,
+        repos::{DiffEntry, DiffEntryStatus, RepoCommit},
+        IssueState,
+    },
+    params, Octocrab,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitFormat {
+    Summary,
+    Detailed,
+    Json,
+}
+
+impl FromStr for CommitFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format.to_lowercase().as_str() {
+            "summary" => Ok(CommitFormat::Summary),
+            "detailed" => Ok(CommitFormat::Detailed),
+            "json" => Ok(CommitFormat::Json),
+            _ => Err(anyhow!("Unknown commit format: {}", format)),
+        }
+    }
+}
 
 pub async fn fetch_latest_commits(
     owner: &str,
@@ -16,16 +47,683 @@ pub async fn fetch_latest_commits(
     Ok(commit)
 }
 
-pub fn process_commit_changes(commit: RepoCommit) -> Result<String> {
+/// Lists commits on `branch` newest first, stopping before `since_sha` or after `limit`
+/// commits. Each commit is fetched individually so that its changed files are included.
+pub async fn fetch_commits_since(
+    owner: &str,
+    repo: &str,
+    branch: &str,
+    since_sha: Option<&str>,
+    limit: usize,
+) -> Result<Vec<RepoCommit>> {
+    let octocrab = github_client()?;
+    let mut page = octocrab
+        .repos(owner, repo)
+        .list_commits()
+        .branch(branch)
+        .per_page(limit.clamp(1, 100) as u8)
+        .send()
+        .await?;
+
+    let mut shas = Vec::new();
+    'pages: loop {
+        for commit in page.items.iter() {
+            if shas.len() >= limit || since_sha.is_some_and(|since| commit.sha.starts_with(since)) {
+                break 'pages;
+            }
+            shas.push(commit.sha.clone());
+        }
+        match octocrab.get_page::<RepoCommit>(&page.next).await? {
+            Some(next_page) => page = next_page,
+            None => break,
+        }
+    }
+
+    let mut commits = Vec::with_capacity(shas.len());
+    for sha in shas {
+        commits.push(octocrab.commits(owner, repo).get(&sha).await?);
+    }
+    Ok(commits)
+}
+
+const GITHUB_GRAPHQL_URL: &str = "https://api.github.com/graphql";
+
+fn github_token() -> Result<String> {
+    dotenv().ok();
+    std::env::var("GITHUB_ACCESS_TOKEN")
+        .map_err(|_| anyhow!("GITHUB_ACCESS_TOKEN env variable is required"))
+}
+
+fn github_client() -> Result<Octocrab> {
+    Ok(Octocrab::builder()
+        .personal_token(github_token()?)
+        .build()?)
+}
+
+/// Returns true if the GraphQL document contains a mutation operation.
+pub fn is_graphql_mutation(query: &str) -> bool {
+    Regex::new(r"(?i)\bmutation\b[^{]*\{")
+        .map(|re| re.is_match(query))
+        .unwrap_or(true)
+}
+
+/// Runs a read-only query against the GitHub GraphQL API. Mutations are rejected.
+pub async fn execute_graphql_query(query: &str, variables: Value) -> Result<Value> {
+    if is_graphql_mutation(query) {
+        return Err(anyhow!("GraphQL mutations are not allowed"));
+    }
+
+    let response = reqwest::Client::new()
+        .post(GITHUB_GRAPHQL_URL)
+        .bearer_auth(github_token()?)
+        .header(reqwest::header::USER_AGENT, "claude-engineer-rs")
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(json!({ "query": query, "variables": variables }).to_string())
+        .send()
+        .await?
+        .error_for_status()?;
+    let body: Value = serde_json::from_str(&response.text().await?)?;
+
+    if let Some(errors) = body.get("errors").filter(|e| !e.is_null()) {
+        return Err(anyhow!("GraphQL query failed: {}", errors));
+    }
+    Ok(body["data"].clone())
+}
+
+pub async fn fetch_github_file(owner: &str, repo: &str, path: &str) -> Result<String> {
+    let octocrab = github_client()?;
+    let mut contents = octocrab
+        .repos(owner, repo)
+        .get_content()
+        .path(path)
+        .send()
+        .await?;
+    let file = contents
+        .take_items()
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("File not found: {}/{}/{}", owner, repo, path))?;
+    file.decoded_content()
+        .ok_or_else(|| anyhow!("Failed to decode content of {}/{}/{}", owner, repo, path))
+}
+
+pub async fn fetch_repo_info(owner: &str, repo: &str) -> Result<String> {
+    let octocrab = github_client()?;
+    let repository = octocrab.repos(owner, repo).get().await?;
+    let or_unknown = |value: Option<String>| value.unwrap_or_else(|| "unknown".to_string());
+
+    Ok(format!(
+        "Repository: {}\nDescription: {}\nTopics: {}\nDefault branch: {}\nStars: {}\nLanguage: {}\nLicense: {}\nOpen issues: {}\nLast push: {}",
+        repository
+            .full_name
+            .unwrap_or_else(|| format!("{}/{}", owner, repo)),
+        or_unknown(repository.description),
+        repository
+            .topics
+            .filter(|topics| !topics.is_empty())
+            .map(|topics| topics.join(", "))
+            .unwrap_or_else(|| "none".to_string()),
+        or_unknown(repository.default_branch),
+        repository.stargazers_count.unwrap_or(0),
+        or_unknown(
+            repository
+                .language
+                .and_then(|language| language.as_str().map(String::from))
+        ),
+        or_unknown(repository.license.map(|license| license.name)),
+        repository.open_issues_count.unwrap_or(0),
+        or_unknown(repository.pushed_at.map(|pushed_at| pushed_at.to_rfc3339())),
+    ))
+}
+
+/// Parses the `open`, `closed` or `all` filter accepted by the pulls and issues APIs.
+fn parse_state(state: &str) -> Result<params::State> {
+    match state.to_lowercase().as_str() {
+        "open" => Ok(params::State::Open),
+        "closed" => Ok(params::State::Closed),
+        "all" => Ok(params::State::All),
+        _ => Err(anyhow!(
+            "Unknown state: {}. Expected open, closed or all",
+            state
+        )),
+    }
+}
+
+fn issue_state_name(state: Option<&IssueState>) -> &'static str {
+    match state {
+        Some(IssueState::Open) => "open",
+        Some(IssueState::Closed) => "closed",
+        _ => "unknown",
+    }
+}
+
+/// Lists pull requests in `state` (`open`, `closed` or `all`), one per line as
+/// `#{number} [{state}] {title} by {author} - {url}`.
+pub async fn fetch_pull_requests(
+    owner: &str,
+    repo: &str,
+    state: &str,
+    per_page: u8,
+) -> Result<String> {
+    let state_filter = parse_state(state)?;
+    let octocrab = github_client()?;
+    let page = octocrab
+        .pulls(owner, repo)
+        .list()
+        .state(state_filter)
+        .per_page(per_page)
+        .send()
+        .await?;
+    if page.items.is_empty() {
+        return Ok(format!("No {} pull requests in {}/{}", state, owner, repo));
+    }
+
+    Ok(page
+        .items
+        .iter()
+        .map(|pr| {
+            format!(
+                "#{} [{}] {} by {} - {}",
+                pr.number,
+                issue_state_name(pr.state.as_ref()),
+                pr.title.as_deref().unwrap_or_default(),
+                pr.user
+                    .as_ref()
+                    .map_or("unknown", |user| user.login.as_str()),
+                pr.html_url
+                    .as_ref()
+                    .map(|url| url.to_string())
+                    .unwrap_or_default()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+/// Lists issues in `state` (`open`, `closed` or `all`) that have all of `labels`, one per
+/// line as `#{number} [{labels}] {title} - {url}`. `since` is an RFC 3339 timestamp;
+/// GitHub then only returns issues updated at or after it. GitHub's issues endpoint also
+/// returns pull requests; they're left out, and count towards `per_page`.
+pub async fn list_issues(
+    owner: &str,
+    repo: &str,
+    state: &str,
+    labels: Vec<String>,
+    per_page: u8,
+    since: Option<String>,
+) -> Result<String> {
+    let state_filter = parse_state(state)?;
+    let since = since
+        .map(|since| {
+            chrono::DateTime::parse_from_rfc3339(&since)
+                .map(|since| since.with_timezone(&chrono::Utc))
+                .map_err(|e| anyhow!("Invalid since timestamp {}: {}", since, e))
+        })
+        .transpose()?;
+    let octocrab = github_client()?;
+    let issues = octocrab.issues(owner, repo);
+    let mut request = issues.list().state(state_filter).per_page(per_page);
+    if !labels.is_empty() {
+        request = request.labels(&labels);
+    }
+    if let Some(since) = since {
+        request = request.since(since);
+    }
+    let page = request.send().await?;
+    Ok(format_issues(&page.items)
+        .unwrap_or_else(|| format!("No {} issues in {}/{}", state, owner, repo)))
+}
+
+/// Formats the issues among `items`, skipping pull requests. `None` if there are none.
+fn format_issues(items: &[Issue]) -> Option<String> {
+    let issues = items
+        .iter()
+        .filter(|issue| issue.pull_request.is_none())
+        .collect::<Vec<_>>();
+    if issues.is_empty() {
+        return None;
+    }
+
+    Some(
+        issues
+            .iter()
+            .map(|issue| {
+                format!(
+                    "#{} [{}] {} - {}",
+                    issue.number,
+                    issue
+                        .labels
+                        .iter()
+                        .map(|label| label.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    issue.title,
+                    issue.html_url
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
+/// Opens a pull request merging `head` into `base` and returns its URL.
+pub async fn create_pull_request(
+    owner: &str,
+    repo: &str,
+    title: &str,
+    body: &str,
+    head: &str,
+    base: &str,
+) -> Result<String> {
+    let octocrab = github_client()?;
+    let pr = octocrab
+        .pulls(owner, repo)
+        .create(title, head, base)
+        .body(body)
+        .send()
+        .await?;
+    pr.html_url.map(|url| url.to_string()).ok_or_else(|| {
+        anyhow!(
+            "GitHub did not return a URL for pull request #{}",
+            pr.number
+        )
+    })
+}
+
+/// An inline comment on a single line of a pull request's diff.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReviewComment {
+    pub path: String,
+    pub line: u32,
+    pub body: String,
+}
+
+const REVIEW_EVENTS: &[&str] = &["COMMENT", "APPROVE", "REQUEST_CHANGES"];
+
+fn normalize_review_event(event: &str) -> Result<String> {
+    let event = event.trim().to_uppercase();
+    if REVIEW_EVENTS.contains(&event.as_str()) {
+        Ok(event)
+    } else {
+        Err(anyhow!(
+            "Unknown review event: {}. Expected one of {}",
+            event,
+            REVIEW_EVENTS.join(", ")
+        ))
+    }
+}
+
+/// Submits a review on a pull request. `event` is `COMMENT`, `APPROVE` or
+/// `REQUEST_CHANGES`. Returns a summary with the review's URL.
+pub async fn create_pull_request_review(
+    owner: &str,
+    repo: &str,
+    pr_number: u64,
+    body: &str,
+    event: &str,
+    comments: Vec<ReviewComment>,
+) -> Result<String> {
+    let event = normalize_review_event(event)?;
+    let octocrab = github_client()?;
+    let review: Value = octocrab
+        .post(
+            format!("/repos/{}/{}/pulls/{}/reviews", owner, repo, pr_number),
+            Some(&json!({
+                "body": body,
+                "event": event,
+                "comments": comments,
+            })),
+        )
+        .await?;
+
+    Ok(format!(
+        "Submitted {} review with {} comments on {}/{}#{}: {}",
+        event,
+        comments.len(),
+        owner,
+        repo,
+        pr_number,
+        review["html_url"].as_str().unwrap_or("(no URL returned)")
+    ))
+}
+
+/// Checks that `workflow_filename` names a file directly inside `.github/workflows`,
+/// so it can't be used to fetch other files in the repository.
+fn validate_workflow_filename(workflow_filename: &str) -> Result<()> {
+    if workflow_filename.is_empty()
+        || workflow_filename == "."
+        || workflow_filename.contains("..")
+        || workflow_filename.contains(['/', '\\'])
+    {
+        return Err(anyhow!(
+            "Invalid workflow filename: {}. Expected a file name such as ci.yml",
+            workflow_filename
+        ));
+    }
+    Ok(())
+}
+
+pub async fn fetch_workflow_file(
+    owner: &str,
+    repo: &str,
+    workflow_filename: &str,
+) -> Result<String> {
+    validate_workflow_filename(workflow_filename)?;
+    fetch_github_file(
+        owner,
+        repo,
+        &format!(".github/workflows/{}", workflow_filename),
+    )
+    .await
+}
+
+/// Files that are skipped unless a filter explicitly names them.
+pub const DEFAULT_EXCLUDED_FILES: &[&str] = &["Cargo.lock", "*.min.js", "*.pb.go"];
+
+fn matches_pattern(filename: &str, pattern: &str) -> bool {
+    let basename = filename.rsplit('/').next().unwrap_or(filename);
+    match pattern.strip_prefix('*') {
+        Some(suffix) => basename.ends_with(suffix),
+        None => basename == pattern,
+    }
+}
+
+pub fn should_include_file(filename: &str, file_filter: Option<&[String]>) -> bool {
+    let explicitly_included = file_filter.map_or(false, |filter| {
+        filter.iter().any(|entry| {
+            matches_pattern(filename, entry)
+                || DEFAULT_EXCLUDED_FILES
+                    .iter()
+                    .any(|excluded| excluded == entry && matches_pattern(filename, excluded))
+        })
+    });
+    if explicitly_included {
+        return true;
+    }
+    if DEFAULT_EXCLUDED_FILES
+        .iter()
+        .any(|excluded| matches_pattern(filename, excluded))
+    {
+        return false;
+    }
+    match file_filter {
+        Some(filter) => filter
+            .iter()
+            .any(|extension| extension.starts_with('.') && filename.ends_with(extension.as_str())),
+        None => true,
+    }
+}
+
+/// Removes files that do not pass `file_filter`, returning the filtered commit and
+/// the number of skipped files.
+pub fn filter_commit_files(
+    mut commit: RepoCommit,
+    file_filter: Option<&[String]>,
+) -> (RepoCommit, usize) {
+    let mut skipped = 0;
+    if let Some(files) = commit.files.as_mut() {
+        let before = files.len();
+        files.retain(|file| should_include_file(&file.filename, file_filter));
+        skipped = before - files.len();
+    }
+    (commit, skipped)
+}
+
+fn change_type(file: &DiffEntry) -> &'static str {
+    match file.status {
+        DiffEntryStatus::Added => "Added",
+        DiffEntryStatus::Modified | DiffEntryStatus::Changed => "Modified",
+        DiffEntryStatus::Removed => "Deleted",
+        DiffEntryStatus::Renamed => "Renamed",
+        _ => "Other",
+    }
+}
+
+/// Summarizes the changes in each commit, leaving out files that do not pass
+/// `file_filter`. Multiple commits are concatenated, each under its own heading.
+pub fn process_commit_changes(
+    commits: Vec<RepoCommit>,
+    file_filter: Option<&[String]>,
+) -> Result<String> {
+    if commits.len() == 1 {
+        let (commit, skipped) =
+            filter_commit_files(commits.into_iter().next().unwrap(), file_filter);
+        return process_single_commit(commit, skipped);
+    }
+
+    let mut result = String::new();
+    for commit in commits {
+        let (commit, skipped) = filter_commit_files(commit, file_filter);
+        let message = commit
+            .commit
+            .message
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        result.push_str(&format!("# Commit {} {}\n\n", commit.sha, message));
+        result.push_str(&process_single_commit(commit, skipped)?);
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+/// Summarizes an already filtered commit, from which `skipped` files were removed.
+fn process_single_commit(commit: RepoCommit, skipped: usize) -> Result<String> {
+    let files = commit.files.unwrap_or_default();
     let mut result = String::new();
-    for file in commit.files.unwrap() {
+    for group in ["Added", "Modified", "Deleted", "Renamed", "Other"] {
+        let group_files = files
+            .iter()
+            .filter(|file| change_type(file) == group)
+            .collect::<Vec<_>>();
+        if group_files.is_empty() {
+            continue;
+        }
+        let additions: u64 = group_files.iter().map(|file| file.additions).sum();
+        let deletions: u64 = group_files.iter().map(|file| file.deletions).sum();
         result.push_str(&format!(
-            "File: {file}, Additions: {additions}, Deletions: {deletions}, Patch: {patch}\n",
-            file = file.filename,
-            additions = file.additions,
-            deletions = file.deletions,
-            patch = file.patch.unwrap_or_default()
+            "## {group} ({count} files, +{additions} -{deletions})\n",
+            count = group_files.len()
         ));
+        for file in group_files {
+            let name = match &file.previous_filename {
+                Some(previous) if group == "Renamed" => {
+                    format!("{} → {}", previous, file.filename)
+                }
+                _ => file.filename.clone(),
+            };
+            result.push_str(&format!(
+                "File: {file}, Additions: {additions}, Deletions: {deletions}, Patch: {patch}\n",
+                file = name,
+                additions = file.additions,
+                deletions = file.deletions,
+                patch = file.patch.clone().unwrap_or_default()
+            ));
+        }
+        result.push('\n');
+    }
+    if skipped > 0 {
+        result.push_str(&format!("Skipped {} files\n", skipped));
     }
     Ok(result)
 }
+
+/// Formats a commit's changes, leaving out files that do not pass `file_filter`. The
+/// number of files left out is included in every format.
+pub fn format_commit(
+    commit: RepoCommit,
+    format: CommitFormat,
+    file_filter: Option<&[String]>,
+) -> Result<Value> {
+    match format {
+        CommitFormat::Summary => {
+            let (commit, skipped) = filter_commit_files(commit, file_filter);
+            let files = commit.files.clone().unwrap_or_default();
+            let additions: u64 = files.iter().map(|file| file.additions).sum();
+            let deletions: u64 = files.iter().map(|file| file.deletions).sum();
+            Ok(Value::String(format!(
+                "SHA: {sha}, +{additions} -{deletions} in {n} files, {skipped} skipped",
+                sha = commit.sha,
+                n = files.len()
+            )))
+        }
+        CommitFormat::Detailed => Ok(Value::String(process_commit_changes(
+            vec![commit],
+            file_filter,
+        )?)),
+        CommitFormat::Json => {
+            let (commit, skipped) = filter_commit_files(commit, file_filter);
+            let files = commit
+                .files
+                .unwrap_or_default()
+                .into_iter()
+                .map(|file| {
+                    json!({
+                        "filename": file.filename,
+                        "additions": file.additions,
+                        "deletions": file.deletions,
+                        "patch": file.patch.unwrap_or_default()
+                    })
+                })
+                .collect::<Vec<_>>();
+            Ok(json!({ "files": files, "skipped": skipped }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_include_file() {
+        let filter = vec![".rs".to_string(), ".toml".to_string()];
+        assert!(should_include_file("src/main.rs", Some(&filter)));
+        assert!(should_include_file("Cargo.toml", Some(&filter)));
+        assert!(!should_include_file("README.md", Some(&filter)));
+        assert!(!should_include_file("Cargo.lock", None));
+        assert!(!should_include_file("web/app.min.js", None));
+        assert!(should_include_file("web/app.js", None));
+
+        let filter = vec!["Cargo.lock".to_string()];
+        assert!(should_include_file("Cargo.lock", Some(&filter)));
+    }
+
+    #[test]
+    fn test_is_graphql_mutation() {
+        assert!(is_graphql_mutation(
+            "mutation { addStar(input: {}) { clientMutationId } }"
+        ));
+        assert!(is_graphql_mutation(
+            "mutation AddStar($id: ID!) { addStar(input: {starrableId: $id}) { clientMutationId } }"
+        ));
+        assert!(!is_graphql_mutation(
+            "query { repository(owner: \"rust-lang\", name: \"rust\") { name } }"
+        ));
+        assert!(!is_graphql_mutation("{ viewer { login } }"));
+    }
+
+    #[test]
+    fn test_validate_workflow_filename() {
+        assert!(validate_workflow_filename("ci.yml").is_ok());
+        assert!(validate_workflow_filename("release.v2.yaml").is_ok());
+        assert!(validate_workflow_filename("../../Cargo.toml").is_err());
+        assert!(validate_workflow_filename("..").is_err());
+        assert!(validate_workflow_filename("nested/ci.yml").is_err());
+        assert!(validate_workflow_filename("..\\secrets.yml").is_err());
+        assert!(validate_workflow_filename("").is_err());
+    }
+
+    #[test]
+    fn test_parse_state() {
+        assert_eq!(parse_state("OPEN").unwrap(), params::State::Open);
+        assert_eq!(parse_state("all").unwrap(), params::State::All);
+        assert!(parse_state("merged").is_err());
+    }
+
+    fn issue(number: u64, is_pull_request: bool) -> Issue {
+        let url = format!(
+            "https://api.github.com/repos/octocat/hello-world/issues/{}",
+            number
+        );
+        let html_url = format!("https://github.com/octocat/hello-world/issues/{}", number);
+        let user = json!({
+            "login": "octocat",
+            "id": 1,
+            "node_id": "MDQ6VXNlcjE=",
+            "avatar_url": "https://github.com/images/error/octocat_happy.gif",
+            "gravatar_id": "",
+            "url": "https://api.github.com/users/octocat",
+            "html_url": "https://github.com/octocat",
+            "followers_url": "https://api.github.com/users/octocat/followers",
+            "following_url": "https://api.github.com/users/octocat/following{/other_user}",
+            "gists_url": "https://api.github.com/users/octocat/gists{/gist_id}",
+            "starred_url": "https://api.github.com/users/octocat/starred{/owner}{/repo}",
+            "subscriptions_url": "https://api.github.com/users/octocat/subscriptions",
+            "organizations_url": "https://api.github.com/users/octocat/orgs",
+            "repos_url": "https://api.github.com/users/octocat/repos",
+            "events_url": "https://api.github.com/users/octocat/events{/privacy}",
+            "received_events_url": "https://api.github.com/users/octocat/received_events",
+            "type": "User",
+            "site_admin": false
+        });
+        let pull_request = is_pull_request.then(|| {
+            let pr_url = format!("https://github.com/octocat/hello-world/pull/{}", number);
+            json!({
+                "url": format!("https://api.github.com/repos/octocat/hello-world/pulls/{}", number),
+                "html_url": pr_url,
+                "diff_url": format!("{}.diff", pr_url),
+                "patch_url": format!("{}.patch", pr_url)
+            })
+        });
+        serde_json::from_value(json!({
+            "id": number,
+            "node_id": "MDU6SXNzdWUx",
+            "url": url,
+            "repository_url": "https://api.github.com/repos/octocat/hello-world",
+            "labels_url": format!("{}/labels{{/name}}", url),
+            "comments_url": format!("{}/comments", url),
+            "events_url": format!("{}/events", url),
+            "html_url": html_url,
+            "number": number,
+            "state": "open",
+            "title": format!("Item {}", number),
+            "body": null,
+            "user": user,
+            "labels": [],
+            "assignee": null,
+            "assignees": [],
+            "milestone": null,
+            "locked": false,
+            "active_lock_reason": null,
+            "comments": 0,
+            "pull_request": pull_request,
+            "closed_at": null,
+            "created_at": "2024-07-01T00:00:00Z",
+            "updated_at": "2024-07-01T00:00:00Z",
+            "author_association": "OWNER"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_format_issues_skips_pull_requests() {
+        let formatted = format_issues(&[issue(1, false), issue(2, true)]).unwrap();
+        assert_eq!(
+            formatted,
+            "#1 [] Item 1 - https://github.com/octocat/hello-world/issues/1"
+        );
+        assert!(format_issues(&[issue(2, true)]).is_none());
+    }
+
+    #[test]
+    fn test_normalize_review_event() {
+        assert_eq!(normalize_review_event("approve").unwrap(), "APPROVE");
+        assert_eq!(
+            normalize_review_event("REQUEST_CHANGES").unwrap(),
+            "REQUEST_CHANGES"
+        );
+        assert!(normalize_review_event("MERGE").is_err());
+    }
+}
@@ -0,0 +1,59 @@
+pub mod commands;
+pub mod config;
+pub mod conversation_manager;
+pub mod crates_io;
+pub mod github_tools;
+pub mod patch;
+pub mod pricing;
+pub mod prompts;
+pub mod rust_docs;
+pub mod store;
+pub mod tools;
+pub mod web_search;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    ToolUseAssistant(Vec<ToolUseAssistant>),
+    ToolUseUser(Vec<ToolUseUser>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ToolUseAssistant {
+    #[serde(rename = "type")]
+    pub tool_type: String,
+    pub id: String,
+    pub name: String,
+    pub input: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ToolUseUser {
+    #[serde(rename = "type")]
+    pub tool_type: String,
+    pub tool_use_id: String,
+    pub content: String,
+}
+
+impl MessageContent {
+    /// Flattens the content into plain text, rendering tool blocks as JSON.
+    pub fn to_plain_text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::ToolUseAssistant(tool_uses) => tool_uses
+                .iter()
+                .map(|tool_use| format!("[tool_use {}] {}", tool_use.name, tool_use.input))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            MessageContent::ToolUseUser(tool_results) => tool_results
+                .iter()
+                .map(|tool_result| format!("[tool_result] {}", tool_result.content))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
@@ -1,27 +1,30 @@
-mod github_tools;
-mod prompts;
 use async_recursion::async_recursion;
-use conversation_manager::Message;
+use claude_engineer_rs::commands::{Command as MetaCommand, CommandParser};
+use claude_engineer_rs::config::{model_from_env, validate_api_key, Config};
+use claude_engineer_rs::conversation_manager::{ConversationManager, ExportFormat, Message};
+use claude_engineer_rs::pricing::{default_pricing, TokenUsage};
+use claude_engineer_rs::prompts::{EditorPromptVersion, Persona, CHAIN_OF_THOUGHT_PROMPT};
+use claude_engineer_rs::tools::{
+    ConflictResolution, DiffDisplayMode, ToolExecutor, CODEEDITORMODEL, TOOLS,
+};
+use claude_engineer_rs::{MessageContent, ToolUseAssistant, ToolUseUser};
 use env_logger::Env;
 use log::debug;
-use prompts::{BASE_SYSTEM_PROMPT, CHAIN_OF_THOUGHT_PROMPT};
 
-use serde_json::Value;
+use serde_json::{json, Value};
 
-mod tools;
-use tools::{ToolExecutor, TOOLS};
-
-mod conversation_manager;
-use conversation_manager::ConversationManager;
-
-// mod language_documentation;
-
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use chrono::{SecondsFormat, Utc};
+use clap::Parser;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 use std::fs;
 use std::io;
-use std::io::Read;
+use std::io::{BufRead, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use uuid::Uuid;
 
 use dotenv::dotenv;
 
@@ -29,36 +32,38 @@ use anthropic_sdk::{AnthropicResponse, Client, ContentItem};
 use log::{error, info, warn};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(untagged)]
-pub enum MessageContent {
-    Text(String),
-    ToolUseAssistant(Vec<ToolUseAssistant>),
-    ToolUseUser(Vec<ToolUseUser>),
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct ToolUseAssistant {
-    #[serde(rename = "type")]
-    tool_type: String,
+pub struct ToolUseResult {
     id: String,
     name: String,
     input: Value,
+    tool_result: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct ToolUseUser {
-    #[serde(rename = "type")]
-    tool_type: String,
-    tool_use_id: String,
-    content: String,
+/// The content of one assistant response, split around its tool calls so the text
+/// Claude writes before and after using tools is kept in order.
+#[derive(Debug, Default)]
+pub struct ProcessedContent {
+    /// Text preceding the first tool call, or all text if no tools were called.
+    pub pre_tool_text: String,
+    pub tool_results: Vec<ToolUseResult>,
+    /// Text following the first tool call.
+    pub post_tool_text: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct ToolUseResult {
-    id: String,
-    name: String,
-    input: Value,
-    tool_result: String,
+impl ProcessedContent {
+    /// The non-empty text segments, in the order Claude wrote them.
+    pub fn text_segments(&self) -> impl Iterator<Item = &str> {
+        [self.pre_tool_text.as_str(), self.post_tool_text.as_str()]
+            .into_iter()
+            .filter(|text| !text.trim().is_empty())
+    }
+}
+
+/// A single API request/response pair written by `Claude::start_recording`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RecordedExchange {
+    request: Value,
+    response: AnthropicResponse,
 }
 
 pub struct Claude {
@@ -66,64 +71,504 @@ pub struct Claude {
     system_prompt: String,
     conversation_manager: ConversationManager,
     tool_executor: ToolExecutor,
+    config: Config,
+    persona: Persona,
+    session_id: Uuid,
+    model: String,
+    token_usage: HashMap<String, u32>,
+    /// Estimated USD cost of the conversation requests so far, each priced for the model
+    /// that served it.
+    cost_usd: f64,
+    /// Conversation token count above which `summarize_history` condenses the oldest
+    /// half of the history.
+    token_budget: usize,
+    beta_flags: Vec<String>,
+    recorder: Option<fs::File>,
+    replay: Option<VecDeque<RecordedExchange>>,
 }
 
-pub const MODEL: &str = "claude-3-5-sonnet-20240620";
+/// Context window sizes in tokens for the models this tool is used with.
+fn context_window_tokens(model: &str) -> usize {
+    match model {
+        "claude-3-5-sonnet-20240620"
+        | "claude-3-opus-20240229"
+        | "claude-3-sonnet-20240229"
+        | "claude-3-haiku-20240307" => 200_000,
+        "claude-2.1" => 200_000,
+        _ => 100_000,
+    }
+}
 pub const CONTINUATION_EXIT_PHRASE: &str = "AUTOMODE_COMPLETE";
 pub const MAX_CONTINUATION_ITERATIONS: i8 = 25;
 
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Copy generated diffs to the system clipboard for review
+    #[arg(long)]
+    clipboard: bool,
+    /// Print the estimated cumulative API cost after each iteration
+    #[arg(long)]
+    print_cost: bool,
+    /// Version of the code editor system prompt to use, e.g. "v1"
+    #[arg(long)]
+    editor_prompt_version: Option<EditorPromptVersion>,
+    /// Back up files before they are modified so `/revert` can restore them
+    #[arg(long)]
+    backup_dir: Option<PathBuf>,
+    /// Continue the conversation saved in this JSON file, and save it there on exit
+    #[arg(long)]
+    session: Option<PathBuf>,
+    /// Name for this session, used in saved filenames. Named sessions are saved as
+    /// JSON on exit even without --session
+    #[arg(long)]
+    session_name: Option<String>,
+    /// Confine file tools to this directory. Relative paths are resolved against it
+    #[arg(long)]
+    working_dir: Option<PathBuf>,
+    /// Summarize the oldest half of the history once the conversation exceeds this
+    /// many tokens
+    #[arg(long)]
+    token_budget: Option<usize>,
+    /// How to show diffs before edits are applied: "unified" or "side-by-side"
+    #[arg(long)]
+    display_mode: Option<DiffDisplayMode>,
+    /// Enable an Anthropic beta feature, e.g. "prompt-caching-2024-07-31". May be repeated
+    #[arg(long = "beta", value_name = "FLAG")]
+    beta: Vec<String>,
+    /// Disable an Anthropic beta feature that is enabled by default. May be repeated
+    #[arg(long = "no-beta", value_name = "FLAG")]
+    no_beta: Vec<String>,
+    /// Append every API request and response to this file as newline-delimited JSON
+    #[arg(long, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+    /// Serve API responses from a file written by --record instead of calling the API
+    #[arg(long)]
+    replay: Option<PathBuf>,
+    /// Append each prompt and response to this file with UTC timestamps
+    #[arg(long)]
+    chat_log: Option<PathBuf>,
+    /// Persona shaping the system prompt, e.g. "architect" or "security-reviewer"
+    #[arg(long)]
+    persona: Option<Persona>,
+    /// What to do with an edit that overlaps an earlier edit in the same batch: "fail",
+    /// "skip", "retry" or "abort"
+    #[arg(long)]
+    on_edit_conflict: Option<ConflictResolution>,
+}
+
+impl Args {
+    /// Reads the config from `CLAUDE_*` environment variables, with the flags given on
+    /// the command line taking precedence.
+    fn into_config(self) -> Result<Config> {
+        let config = Config::from_env()?;
+        Ok(Config {
+            clipboard: self.clipboard || config.clipboard,
+            editor_prompt_version: self
+                .editor_prompt_version
+                .unwrap_or(config.editor_prompt_version),
+            backup_dir: self.backup_dir.or(config.backup_dir),
+            working_dir: self.working_dir.or(config.working_dir),
+            display_mode: self.display_mode.unwrap_or(config.display_mode),
+            edit_conflict_resolution: self
+                .on_edit_conflict
+                .unwrap_or(config.edit_conflict_resolution),
+            ..config
+        })
+    }
+}
+
 impl Claude {
     pub fn new(model: &str) -> Result<Self> {
+        Self::with_config(model, Config::default())
+    }
+
+    pub fn with_config(model: &str, config: Config) -> Result<Self> {
         dotenv().ok();
 
         let api_key = std::env::var("ANTHROPIC_API_KEY_RS")
             .context("Failed to get ANTHROPIC_API_KEY_RS from environment")?;
+        validate_api_key(&api_key)?;
         // .beta("max-tokens-3-5-sonnet-2024-07-15")
         let client = Client::new()
             .auth(&api_key)
             .model(model)
-            .max_tokens(4000)
-            .tools(&TOOLS)
-            .beta("prompt-caching-2024-07-31");
-        let system_prompt = format!(
-            r#"
-            {}
-            {}"#,
-            BASE_SYSTEM_PROMPT, CHAIN_OF_THOUGHT_PROMPT
-        );
-        let tool_client = client.clone().system(&system_prompt.clone());
-        let tool_executor =
-            ToolExecutor::new(tool_client).context("Failed to create ToolExecutor")?;
-        let conversation_manager = ConversationManager::new(1000);
+            .max_tokens(config.max_tokens)
+            .tools(&TOOLS);
+        let persona = Persona::SoftwareEngineer;
+        let system_prompt = Self::build_system_prompt(persona);
+        let session_id = Uuid::new_v4();
+        let mut tool_executor = ToolExecutor::new_lazy(&api_key, CODEEDITORMODEL)
+            .context("Failed to create ToolExecutor")?;
+        tool_executor.set_editor_beta_flags(config.beta_flags.clone());
+        tool_executor.set_session_id(session_id);
+        tool_executor.set_clipboard(config.clipboard);
+        tool_executor.set_max_file_size_bytes(config.max_file_size_kb * 1024);
+        tool_executor.set_rate_limits(config.read_ops_per_second, config.write_ops_per_second);
+        tool_executor.set_auto_apply(config.auto_apply);
+        tool_executor.set_dry_run(config.dry_run);
+        tool_executor.set_batch_edit_min_lines(config.batch_edit_min_lines);
+        tool_executor.set_use_color(config.use_color);
+        tool_executor.set_cache_ttl(config.cache_ttl);
+        tool_executor.set_prompt_version(config.editor_prompt_version);
+        tool_executor.set_backup_dir(config.backup_dir.clone());
+        tool_executor.set_working_dir(config.working_dir.clone());
+        tool_executor.set_audit_log(config.audit_log.clone());
+        tool_executor.set_follow_symlinks(config.follow_symlinks);
+        tool_executor.set_tool_use_count_limits(config.tool_use_count_limit.clone());
+        tool_executor.set_display_mode(config.display_mode);
+        tool_executor.set_conflict_resolution(config.edit_conflict_resolution);
+        tool_executor.reset_tool_use_counts();
+        let mut conversation_manager = ConversationManager::new(config.max_history);
+        conversation_manager.set_chat_filename_format(&config.chat_filename_format);
+        conversation_manager.set_chat_template(config.chat_template.clone());
+        conversation_manager.set_persona(persona.name());
+        conversation_manager.set_session_id(session_id);
+        info!("session={} Created new Claude session", session_id);
         Ok(Self {
             client,
             system_prompt,
             conversation_manager,
             tool_executor,
+            beta_flags: config.beta_flags.clone(),
+            token_budget: config.token_budget,
+            config,
+            persona,
+            session_id,
+            model: model.to_string(),
+            token_usage: HashMap::new(),
+            cost_usd: 0.0,
+            recorder: None,
+            replay: None,
         })
     }
 
+    /// Enables an Anthropic beta feature, e.g. `"prompt-caching-2024-07-31"`, for both
+    /// conversation and code editor requests.
+    pub fn with_beta(mut self, flag: &str) -> Self {
+        if !self.beta_flags.iter().any(|f| f == flag) {
+            self.beta_flags.push(flag.to_string());
+        }
+        self.tool_executor
+            .set_editor_beta_flags(self.beta_flags.clone());
+        self
+    }
+
+    /// Disables a previously enabled Anthropic beta feature.
+    pub fn without_beta(mut self, flag: &str) -> Self {
+        self.beta_flags.retain(|f| f != flag);
+        self.tool_executor
+            .set_editor_beta_flags(self.beta_flags.clone());
+        self
+    }
+
+    fn apply_beta_flags(client: Client, beta_flags: &[String]) -> Client {
+        if beta_flags.is_empty() {
+            client
+        } else {
+            client.beta(&beta_flags.join(","))
+        }
+    }
+
+    /// Appends every subsequent API request/response pair to `path` as newline-delimited JSON.
+    pub fn start_recording(&mut self, path: &str) -> Result<()> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open recording file {}", path))?;
+        info!("session={} Recording session to {}", self.session_id, path);
+        self.recorder = Some(file);
+        Ok(())
+    }
+
+    /// Serves subsequent API calls from a recording made with `start_recording`
+    /// instead of calling the API.
+    pub fn replay_from_recording(&mut self, path: &str) -> Result<()> {
+        let file = fs::File::open(path)
+            .with_context(|| format!("Failed to open recording file {}", path))?;
+        let exchanges = io::BufReader::new(file)
+            .lines()
+            .filter(|line| line.as_ref().map_or(true, |l| !l.trim().is_empty()))
+            .map(|line| Ok(serde_json::from_str::<RecordedExchange>(&line?)?))
+            .collect::<Result<VecDeque<_>>>()
+            .context("Failed to parse recording")?;
+        info!(
+            "session={} Replaying {} recorded exchanges from {}",
+            self.session_id,
+            exchanges.len(),
+            path
+        );
+        self.replay = Some(exchanges);
+        Ok(())
+    }
+
+    async fn send_messages(&mut self, messages: &Value) -> Result<AnthropicResponse> {
+        if let Some(replay) = self.replay.as_mut() {
+            let exchange = replay
+                .pop_front()
+                .ok_or(anyhow!("Recorded session has no more responses"))?;
+            info!("session={} Replaying recorded response", self.session_id);
+            return Ok(exchange.response);
+        }
+
+        let request = Self::apply_beta_flags(self.client.clone(), &self.beta_flags)
+            .messages(messages)
+            .system(&self.system_prompt)
+            .build()
+            .context("Failed to build Anthropic request")?;
+        let response = request.execute_and_return_json().await?;
+
+        match self.recorder.as_mut() {
+            Some(recorder) => {
+                let exchange = RecordedExchange {
+                    request: json!({ "system": self.system_prompt, "messages": messages }),
+                    response,
+                };
+                writeln!(recorder, "{}", serde_json::to_string(&exchange)?)?;
+                Ok(exchange.response)
+            }
+            None => Ok(response),
+        }
+    }
+
+    fn record_usage(&mut self, response: &AnthropicResponse) {
+        self.record_billed_usage(response);
+        self.conversation_manager
+            .record_input_tokens(response.usage.input_tokens as usize);
+        info!(
+            "session={} Conversation token count: {}",
+            self.session_id,
+            self.conversation_manager.total_token_count()
+        );
+    }
+
+    /// Adds a response's tokens to the session totals used for cost estimates.
+    fn record_billed_usage(&mut self, response: &AnthropicResponse) {
+        self.token_usage
+            .entry("input".to_string())
+            .and_modify(|e| *e += response.usage.input_tokens)
+            .or_insert(response.usage.input_tokens);
+        self.token_usage
+            .entry("output".to_string())
+            .and_modify(|e| *e += response.usage.output_tokens)
+            .or_insert(response.usage.output_tokens);
+        match serde_json::to_value(&response.usage) {
+            Ok(usage) => {
+                self.cost_usd +=
+                    default_pricing(&self.model).cost_usd(&TokenUsage::from_json(&usage))
+            }
+            Err(e) => warn!("Failed to read token usage for the cost estimate: {}", e),
+        }
+    }
+
+    pub fn set_token_budget(&mut self, token_budget: usize) {
+        self.token_budget = token_budget;
+    }
+
+    pub fn set_model(&mut self, model: &str) {
+        info!("session={} Switching model to {}", self.session_id, model);
+        self.client = self.client.clone().model(model);
+        self.model = model.to_string();
+    }
+
+    /// Estimated USD cost of the session so far, including code editor calls.
+    pub fn estimate_cost_usd(&self) -> f64 {
+        self.cost_usd + self.tool_executor.code_editor_cost_usd()
+    }
+
+    pub fn print_status(&self, iteration: usize) {
+        let editor_tokens = self.tool_executor.code_editor_tokens();
+        println!(
+            "Session: {}\nModel: {}\nPersona: {}\nIterations: {}\nMessages: {}\nTokens: {} input, {} output\nEditor tokens: {} input, {} output",
+            self.session_id,
+            self.model,
+            self.persona.name(),
+            iteration,
+            self.conversation_manager.message_count(),
+            self.token_usage.get("input").unwrap_or(&0),
+            self.token_usage.get("output").unwrap_or(&0),
+            editor_tokens.get("input").unwrap_or(&0),
+            editor_tokens.get("output").unwrap_or(&0),
+        );
+    }
+
+    /// Tokens available for conversation messages once the system prompt, tool
+    /// definitions and the response are accounted for.
+    fn conversation_budget(&self) -> usize {
+        let overhead = (self.system_prompt.len() + TOOLS.to_string().len()) / 4;
+        context_window_tokens(&self.model)
+            .saturating_sub(self.config.max_tokens.max(0) as usize)
+            .saturating_sub(overhead)
+    }
+
+    pub fn print_history(&self, exchanges: usize) {
+        let conversation = self.conversation_manager.get_combined_conversation();
+        let start = conversation.len().saturating_sub(exchanges * 2);
+        for (index, message) in conversation.iter().enumerate().skip(start) {
+            println!(
+                "[{}] {}: {}\n",
+                index,
+                message.role,
+                message.content.to_plain_text()
+            );
+        }
+    }
+
+    pub fn print_search_results(&self, query: &str) {
+        let matches = self.conversation_manager.search_history(query, false);
+        if matches.is_empty() {
+            println!("No messages match {}", query);
+        }
+        for (index, message) in matches {
+            println!(
+                "[{}] {}: {}\n",
+                index,
+                message.role,
+                message.content.to_plain_text()
+            );
+        }
+    }
+
+    /// Handles a meta-command from the iteration menu, returning `false` if the
+    /// command was not a meta-command.
+    pub fn handle_meta_command(&mut self, command: &MetaCommand, iteration: usize) -> bool {
+        match command {
+            MetaCommand::Status => self.print_status(iteration),
+            MetaCommand::Search => {
+                println!("Search for:");
+                let mut query = String::new();
+                match io::stdin().read_line(&mut query) {
+                    Ok(_) => self.print_search_results(query.trim()),
+                    Err(e) => println!("Failed to read query: {}", e),
+                }
+            }
+            MetaCommand::Branch => match self.branch() {
+                Ok(path) => println!(
+                    "Saved the previous branch to {} and started branch {}",
+                    path,
+                    self.conversation_manager.branch_id()
+                ),
+                Err(e) => println!("Branch failed: {}", e),
+            },
+            MetaCommand::Undo => match self.tool_executor.restore_backup() {
+                Ok(message) => println!("{}", message),
+                Err(e) => println!("Undo failed: {}", e),
+            },
+            MetaCommand::Revert => match self.tool_executor.revert_all_edits() {
+                Ok(reverted) if reverted.is_empty() => println!("No edits to revert"),
+                Ok(reverted) => println!("Reverted:\n{}", reverted.join("\n")),
+                Err(e) => println!("Revert failed: {}", e),
+            },
+            MetaCommand::Save => match self.save_chat() {
+                Ok(filename) => println!("Chat saved to {}", filename),
+                Err(e) => println!("Failed to save chat: {}", e),
+            },
+            MetaCommand::Model(model) => self.set_model(model),
+            MetaCommand::Checkpoint(name) => {
+                let position = self.conversation_manager.create_checkpoint(name);
+                println!("Checkpoint {} created at message {}", name, position);
+            }
+            MetaCommand::Restore(name) => {
+                match self.conversation_manager.restore_to_checkpoint(name) {
+                    Ok(()) => println!("Restored conversation to checkpoint {}", name),
+                    Err(e) => println!("Restore failed: {}", e),
+                }
+            }
+            MetaCommand::Tools => {
+                for tool in TOOLS.as_array().into_iter().flatten() {
+                    println!(
+                        "{}: {}",
+                        tool["name"].as_str().unwrap_or_default(),
+                        tool["description"].as_str().unwrap_or_default()
+                    );
+                }
+            }
+            MetaCommand::History => self.print_history(5),
+            MetaCommand::Clear => self.conversation_manager.clear_history(),
+            MetaCommand::Help => println!("{}", CommandParser::help()),
+            MetaCommand::Unknown(input) => {
+                println!("Unknown command: {}\n{}", input, CommandParser::help())
+            }
+            MetaCommand::Continue | MetaCommand::Exit | MetaCommand::NewPrompt => return false,
+        }
+        true
+    }
+
+    pub fn session_id(&self) -> Uuid {
+        self.session_id
+    }
+
+    fn build_system_prompt(persona: Persona) -> String {
+        format!(
+            r#"
+            {}
+            {}"#,
+            persona.base_system_prompt(),
+            CHAIN_OF_THOUGHT_PROMPT
+        )
+    }
+
+    pub fn with_persona(mut self, persona: Persona) -> Self {
+        info!("Using persona: {}", persona.name());
+        self.persona = persona;
+        self.system_prompt = Self::build_system_prompt(persona);
+        self.conversation_manager.set_persona(persona.name());
+        self
+    }
+
     pub async fn process_content_response(
         &mut self,
         content: Vec<ContentItem>,
-    ) -> Result<(String, Vec<ToolUseResult>)> {
-        let mut response_text = String::new();
-        let mut tool_results: Vec<ToolUseResult> = vec![];
+    ) -> Result<ProcessedContent> {
+        // Commit fetches are independent of each other, so when several are requested
+        // at once they are fetched concurrently up front.
+        let commit_fetches = content
+            .iter()
+            .filter_map(|item| match item {
+                ContentItem::ToolUse { id, name, input } if name == "fetch_commit_changes" => {
+                    Some((id.clone(), input))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut prefetched = HashMap::new();
+        if commit_fetches.len() > 1 {
+            let inputs = commit_fetches
+                .iter()
+                .map(|(_, input)| *input)
+                .collect::<Vec<_>>();
+            let results = self
+                .tool_executor
+                .fetch_commit_changes_concurrently(&inputs)
+                .await
+                .context("Failed to execute tool: fetch_commit_changes")?;
+            prefetched.extend(commit_fetches.into_iter().map(|(id, _)| id).zip(results));
+        }
+
+        let mut processed = ProcessedContent::default();
         for item in content {
             match item {
                 ContentItem::Text { text } => {
                     info!("Assistant: {}", text);
-                    response_text.push_str(&text);
+                    if processed.tool_results.is_empty() {
+                        processed.pre_tool_text.push_str(&text);
+                    } else {
+                        processed.post_tool_text.push_str(&text);
+                    }
                 }
                 ContentItem::ToolUse { id, name, input } => {
                     info!("Tool Use: {} ({}), Input: {:?}", name, id, input);
-                    let tool_result = self
-                        .tool_executor
-                        .execute_tool(&name, &input)
-                        .await
-                        .with_context(|| format!("Failed to execute tool: {}", name))?;
+                    let tool_result = match prefetched.remove(&id) {
+                        Some(tool_result) => tool_result,
+                        None => self
+                            .tool_executor
+                            .execute_tool(&name, &input)
+                            .await
+                            .with_context(|| format!("Failed to execute tool: {}", name))?,
+                    };
 
-                    tool_results.push(ToolUseResult {
+                    processed.tool_results.push(ToolUseResult {
                         id,
                         name,
                         input,
@@ -132,142 +577,310 @@ impl Claude {
                 }
             }
         }
-        Ok((response_text, tool_results))
+        Ok(processed)
     }
 
     pub async fn ask_claude_simple(&mut self, prompt: &str) -> Result<AnthropicResponse> {
-        info!("Calling ask_claude_simple function");
-
-        self.conversation_manager.clear_current();
-
-        self.conversation_manager.add_to_current(Message {
-            role: "user".to_string(),
-            content: MessageContent::Text(prompt.to_string()),
-        });
-        info!("Added new message to current conversation");
-
-        let combined_conversation = self.conversation_manager.get_combined_conversation();
         info!(
-            "Combined conversation message count: {}",
-            combined_conversation.len()
+            "session={} Calling ask_claude_simple function",
+            self.session_id
         );
 
-        let messages =
-            serde_json::to_value(&combined_conversation).context("Failed to serialize messages")?;
-        info!("Serialized messages for Anthropic request");
+        self.conversation_manager
+            .add_to_current(Message::text("user", prompt));
+        info!(
+            "session={} Added new message to current conversation",
+            self.session_id
+        );
 
-        let request = self
-            .client
-            .clone()
-            .messages(&messages)
-            .system(&self.system_prompt)
-            .build()
-            .context("Failed to build Anthropic request")?;
-        info!("Built Anthropic request");
+        let budget = self.conversation_budget();
+        let messages = self
+            .conversation_manager
+            .to_anthropic_messages_value(budget)?;
+        info!(
+            "session={} Serialized messages for Anthropic request",
+            self.session_id
+        );
 
-        match request.execute_and_return_json().await {
+        match self.send_messages(&messages).await {
             Ok(res) => {
-                info!("Successfully executed Anthropic request");
+                self.record_usage(&res);
+                info!(
+                    "session={} Successfully executed Anthropic request",
+                    self.session_id
+                );
                 Ok(res)
             }
             Err(e) => {
-                error!("Failed to execute Anthropic request: {:?}", e);
+                error!(
+                    "session={} Failed to execute Anthropic request: {:?}",
+                    self.session_id, e
+                );
                 Err(e.into())
             }
         }
     }
 
+    /// Asks Claude to summarize the oldest `count` history messages and replaces them
+    /// with the summary. The messages are sent in a request of their own rather than
+    /// with the conversation.
+    pub async fn summarize_history(&mut self, count: usize) -> Result<()> {
+        let messages = self.conversation_manager.oldest_history(count);
+        if messages.is_empty() {
+            return Ok(());
+        }
+        let transcript = messages
+            .iter()
+            .map(|message| format!("{}: {}", message.role, message.content.to_plain_text()))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let prompt = format!("Summarize these messages in 200 words:\n\n{}", transcript);
+        let response = self
+            .send_messages(&json!([{ "role": "user", "content": prompt }]))
+            .await
+            .context("Failed to summarize history")?;
+        self.record_billed_usage(&response);
+        let summary = response
+            .content
+            .into_iter()
+            .filter_map(|item| match item {
+                ContentItem::Text { text } => Some(text),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("");
+
+        self.conversation_manager
+            .replace_oldest_with_summary(messages.len(), summary.trim());
+        info!(
+            "session={} Summarized {} history messages",
+            self.session_id,
+            messages.len()
+        );
+        Ok(())
+    }
+
+    pub async fn ask_for_json<T: DeserializeOwned>(
+        &mut self,
+        prompt: &str,
+        schema_hint: &str,
+    ) -> Result<T> {
+        let prompt = format!(
+            "{}\n\nRespond with only valid JSON matching the schema: {}",
+            prompt, schema_hint
+        );
+        let response = self.ask_claude_simple(&prompt).await?;
+        let text = response
+            .content
+            .into_iter()
+            .filter_map(|item| match item {
+                ContentItem::Text { text } => Some(text),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("");
+
+        serde_json::from_str::<T>(strip_code_fences(&text))
+            .with_context(|| format!("Failed to parse JSON response: {}", text))
+    }
+
     pub async fn ask_claude_tool(
         &mut self,
         tool_results: Vec<ToolUseResult>,
     ) -> Result<AnthropicResponse> {
         info!("Tool usages: {:?}", &tool_results);
-        for tool_usage in tool_results {
-            self.conversation_manager.add_to_current(Message {
-                role: "assistant".to_string(),
-                content: MessageContent::ToolUseAssistant(vec![ToolUseAssistant {
-                    tool_type: "tool_use".to_string(),
-                    id: tool_usage.id.clone(),
-                    name: tool_usage.name.clone(),
-                    input: tool_usage.input.clone(),
-                }]),
-            });
-
-            self.conversation_manager.add_to_current(Message {
-                role: "user".to_string(),
-                content: MessageContent::ToolUseUser(vec![ToolUseUser {
-                    tool_type: "tool_result".to_string(),
-                    tool_use_id: tool_usage.id.clone(),
-                    content: tool_usage.tool_result,
-                }]),
-            });
+        // All tool calls from one response go back as a single assistant message and all
+        // of their results as a single user message, as the API expects.
+        if !tool_results.is_empty() {
+            self.conversation_manager.add_to_current(Message::new(
+                "assistant",
+                MessageContent::ToolUseAssistant(
+                    tool_results
+                        .iter()
+                        .map(|tool_usage| ToolUseAssistant {
+                            tool_type: "tool_use".to_string(),
+                            id: tool_usage.id.clone(),
+                            name: tool_usage.name.clone(),
+                            input: tool_usage.input.clone(),
+                        })
+                        .collect(),
+                ),
+            ));
+
+            self.conversation_manager.add_to_current(Message::new(
+                "user",
+                MessageContent::ToolUseUser(
+                    tool_results
+                        .into_iter()
+                        .map(|tool_usage| ToolUseUser {
+                            tool_type: "tool_result".to_string(),
+                            tool_use_id: tool_usage.id,
+                            content: tool_usage.tool_result,
+                        })
+                        .collect(),
+                ),
+            ));
         }
 
-        let combined_conversation = self.conversation_manager.get_combined_conversation();
-        info!(
-            "Combined conversation length: {}",
-            combined_conversation.len()
-        );
-        let messages = serde_json::to_value(&combined_conversation)
+        let budget = self.conversation_budget();
+        let messages = self
+            .conversation_manager
+            .to_anthropic_messages_value(budget)
             .context("Failed to serialize messages after tool use")?;
 
-        let request = self
-            .client
-            .clone()
-            .messages(&messages)
-            .system(&self.system_prompt)
-            .build()
-            .context("Failed to build Anthropic request after tool use")?;
-
-        let res = request
-            .execute_and_return_json()
+        let res = self
+            .send_messages(&messages)
             .await
             .context("Failed to execute Anthropic request after tool use")?;
+        self.record_usage(&res);
         info!("Tool result: {:?}", res);
         Ok(res)
     }
 
+    /// Continues the conversation saved at `path` by `save_session`. Its messages,
+    /// including an exchange that was in progress, become this conversation's history.
+    pub fn load_session(&mut self, path: &str) -> Result<()> {
+        let mut saved = ConversationManager::load_session(path)?;
+        saved.commit_current_to_history();
+        if let (None, Some(name)) = (self.conversation_manager.name(), saved.name()) {
+            self.conversation_manager.set_name(name);
+        }
+        let manager =
+            std::mem::replace(&mut self.conversation_manager, ConversationManager::new(0));
+        self.conversation_manager = manager.merge(saved);
+        Ok(())
+    }
+
+    /// Continues on a new branch of the conversation, first saving the current branch to
+    /// `branch_<id>.json` so it can be resumed with `load_session`. Returns that path.
+    pub fn branch(&mut self) -> Result<String> {
+        let path = format!("branch_{}.json", self.conversation_manager.branch_id());
+        self.conversation_manager.save_session(&path)?;
+        self.conversation_manager = self.conversation_manager.branch();
+        Ok(path)
+    }
+
+    /// Saves the chat in the configured `export_format` and returns the filename.
+    pub fn save_chat(&self) -> io::Result<String> {
+        match self.config.export_format {
+            ExportFormat::Markdown => self.conversation_manager.save_chat(),
+            ExportFormat::Html => self.conversation_manager.save_chat_html(),
+        }
+    }
+
+    pub fn set_session_name(&mut self, name: &str) {
+        self.conversation_manager.set_name(name);
+    }
+
+    pub fn session_name(&self) -> Option<&str> {
+        self.conversation_manager.name()
+    }
+
+    pub fn save_session(&self, path: &str) -> Result<()> {
+        self.conversation_manager.save_session(path)
+    }
+
     pub fn commit_conversation(&mut self) {
         self.conversation_manager.commit_current_to_history();
+        self.tool_executor.discard_rollback_backups();
     }
 
     #[async_recursion]
     pub async fn chat_with_claude(&mut self, prompt: &str) -> Result<String> {
+        if self.conversation_manager.total_token_count() > self.token_budget {
+            let count = self
+                .conversation_manager
+                .next_turn_start(self.conversation_manager.history_len() / 2);
+            if let Err(e) = self.summarize_history(count).await {
+                warn!(
+                    "session={} Failed to summarize history: {:?}",
+                    self.session_id, e
+                );
+            }
+        }
         let response = match self.ask_claude_simple(prompt).await {
             Ok(anthropic_response) => {
-                info!("Anthropic response: {:?}", anthropic_response);
-                let (response_text, tool_usages) = self
-                    .process_content_response(anthropic_response.content)
-                    .await?;
-
-                let tool_result = self.ask_claude_tool(tool_usages).await?;
-
-                if tool_result.stop_reason == "tool_use" {
-                    let (response_text, tool_usages) =
-                        self.process_content_response(tool_result.content).await?;
-                    let tool_result = self.ask_claude_tool(tool_usages).await?;
-                    if tool_result.stop_reason == "tool_use" {
-                        return Ok(response_text);
+                info!(
+                    "session={} Anthropic response: {:?}",
+                    self.session_id, anthropic_response
+                );
+                let mut response = anthropic_response;
+                let mut segments: Vec<String> = Vec::new();
+                let mut tool_rounds = 0;
+                // Keep sending tool results back until Claude answers without using a tool.
+                loop {
+                    let uses_tools = response.stop_reason == "tool_use";
+                    let processed = self.process_content_response(response.content).await?;
+                    segments.extend(processed.text_segments().map(str::to_string));
+                    if !uses_tools {
+                        break;
                     }
+                    if tool_rounds == MAX_CONTINUATION_ITERATIONS {
+                        warn!(
+                            "session={} Stopped after {} rounds of tool use",
+                            self.session_id, tool_rounds
+                        );
+                        break;
+                    }
+                    tool_rounds += 1;
+                    response = self.ask_claude_tool(processed.tool_results).await?;
                 }
 
-                Ok(response_text)
+                let reply = segments.join("\n\n");
+                if !reply.is_empty() {
+                    self.conversation_manager
+                        .add_to_current(Message::text("assistant", reply.clone()));
+                }
+                Ok(reply)
             }
             Err(e) => {
                 if e.to_string()
                     .contains("Too many Requests. You have been rate limited.")
                 {
-                    warn!("Rate limited. Waiting for 5 seconds before retrying...");
+                    warn!(
+                        "session={} Rate limited. Waiting for 5 seconds before retrying...",
+                        self.session_id
+                    );
                     tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+                    // The retry adds the prompt again.
+                    self.conversation_manager.clear_current();
                     return self.chat_with_claude(prompt).await;
                 }
-                error!("Execution failed: {:?}", e);
+                error!("session={} Execution failed: {:?}", self.session_id, e);
                 Err(e.context("Failed to execute query with tools"))
             }
         }?;
         Ok(response)
     }
 
+    /// Runs `chat_with_claude` and appends the turn to `log_path` with UTC timestamps.
+    pub async fn chat_with_claude_logged(
+        &mut self,
+        prompt: &str,
+        log_path: &str,
+    ) -> Result<String> {
+        let sent_at = Utc::now();
+        let response = self.chat_with_claude(prompt).await?;
+        let received_at = Utc::now();
+
+        let mut log_file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path)
+            .with_context(|| format!("Failed to open chat log {}", log_path))?;
+        write!(
+            log_file,
+            "[{}] USER: {}\n[{}] ASSISTANT: {}\n---\n",
+            sent_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            prompt,
+            received_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            response
+        )
+        .context("Failed to write to chat log")?;
+
+        Ok(response)
+    }
+
     pub fn load_existing_prompt(&mut self, file_path: &str) -> Result<String> {
         let file = match fs::File::open(file_path).context("Failed to open prompt.txt") {
             Ok(file) => file,
@@ -317,14 +930,81 @@ impl Claude {
     }
 }
 
+fn strip_code_fences(text: &str) -> &str {
+    let trimmed = text.trim();
+    match trimmed.strip_prefix("```") {
+        Some(rest) => {
+            let rest = rest.split_once('\n').map_or("", |(_, body)| body);
+            rest.trim_end().strip_suffix("```").unwrap_or(rest).trim()
+        }
+        None => trimmed,
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
     info!("Starting the program");
 
-    let mut claude = Claude::new(MODEL).context("Failed to initialize Claude")?;
-    info!("Claude instance initialized with model: {}", MODEL);
+    // Before parsing, so settings in `.env` are seen by `Config::from_env`.
+    dotenv().ok();
+    let args = Args::parse();
+    let print_cost = args.print_cost;
+    let token_budget = args.token_budget;
+    let session_name = args.session_name.clone();
+    let enabled_betas = args.beta.clone();
+    let disabled_betas = args.no_beta.clone();
+    let record = args.record.clone();
+    let replay = args.replay.clone();
+    let persona = args.persona;
+    let chat_log = args
+        .chat_log
+        .as_ref()
+        .map(|path| path.to_string_lossy().into_owned());
+    let session = args
+        .session
+        .as_ref()
+        .map(|path| path.to_string_lossy().into_owned());
+    let model = model_from_env();
+    let config = args.into_config().context("Invalid configuration")?;
+    let mut claude = Claude::with_config(&model, config).context("Failed to initialize Claude")?;
+    if let Some(persona) = persona {
+        claude = claude.with_persona(persona);
+    }
+    for flag in &disabled_betas {
+        claude = claude.without_beta(flag);
+    }
+    for flag in &enabled_betas {
+        claude = claude.with_beta(flag);
+    }
+    info!(
+        "session={} Claude instance initialized with model: {}",
+        claude.session_id(),
+        model
+    );
+    if let Some(token_budget) = token_budget {
+        claude.set_token_budget(token_budget);
+    }
+    if let Some(name) = &session_name {
+        claude.set_session_name(name);
+    }
+    if let Some(path) = &record {
+        claude
+            .start_recording(&path.to_string_lossy())
+            .context("Failed to start recording")?;
+    }
+    if let Some(path) = &replay {
+        claude
+            .replay_from_recording(&path.to_string_lossy())
+            .context("Failed to load recording")?;
+    }
+
+    if let Some(session) = session.as_deref().filter(|path| Path::new(path).exists()) {
+        claude
+            .load_session(session)
+            .context("Failed to load session")?;
+    }
 
     let mut prompt = claude
         .load_text_editor()
@@ -332,52 +1012,57 @@ async fn main() -> Result<()> {
     info!("Text editor loaded successfully");
 
     let mut iteration = 0;
-    loop {
+    'outer: loop {
         if iteration > 0 {
-            info!(
-                r#"
-                Starting a new iteration. How would you like to proceed?
-                c: Continue from the last response
-                e: Exit the program
-                n: Input a new prompt
-            "#
-            );
-
-            let mut input = String::new();
-            io::stdin().read_line(&mut input)?;
+            info!("{}", CommandParser::help());
 
-            let command = input.trim().to_lowercase();
+            loop {
+                let mut input = String::new();
+                io::stdin().read_line(&mut input)?;
 
-            match command.as_str() {
-                "c" => {
-                    info!("Continuing from the last response");
-                }
-                "e" => {
-                    info!("Exiting the program");
-                    break;
-                }
-                "n" => {
-                    info!("Inputting a new prompt");
-                    prompt = claude
-                        .load_text_editor()
-                        .context("Failed to load text editor")?;
+                let command = CommandParser::parse(&input);
+                if claude.handle_meta_command(&command, iteration) {
+                    continue;
                 }
-                _ => {
-                    info!("Invalid command. Continuing from the last response");
-                    panic!("Invalid command");
+
+                match command {
+                    MetaCommand::Continue => {
+                        info!("Continuing from the last response");
+                    }
+                    MetaCommand::Exit => {
+                        info!("Exiting the program");
+                        break 'outer;
+                    }
+                    MetaCommand::NewPrompt => {
+                        info!("Inputting a new prompt");
+                        prompt = claude
+                            .load_text_editor()
+                            .context("Failed to load text editor")?;
+                    }
+                    _ => unreachable!("meta-commands are handled above"),
                 }
+                break;
             }
         }
 
-        info!("Starting iteration {}", iteration);
+        match claude.session_name() {
+            Some(name) => info!("Starting iteration {} of session {}", iteration, name),
+            None => info!("Starting iteration {}", iteration),
+        }
         info!("Processing contents: {}", &prompt);
 
-        match claude.chat_with_claude(&prompt).await {
+        let result = match &chat_log {
+            Some(log_path) => claude.chat_with_claude_logged(&prompt, log_path).await,
+            None => claude.chat_with_claude(&prompt).await,
+        };
+        match result {
             Ok(response) => {
                 info!(
                     "Received response from Claude (iteration {}): {}",
                     iteration, &response
                 );
+                // Edits made this turn can no longer be rolled back once it ends.
+                claude.commit_conversation();
                 if response.contains(CONTINUATION_EXIT_PHRASE) {
                     info!("Exit phrase detected. Exiting the loop.");
                     break;
@@ -394,9 +1079,28 @@ async fn main() -> Result<()> {
             }
         }
 
+        if print_cost {
+            println!("Estimated cost so far: ${:.4}", claude.estimate_cost_usd());
+        }
+
         iteration += 1;
     }
 
+    let session = session.or_else(|| {
+        claude
+            .session_name()
+            .map(|_| claude.conversation_manager.session_filename())
+    });
+    if let Some(session) = &session {
+        claude
+            .save_session(session)
+            .context("Failed to save session")?;
+        println!("Session saved to {}", session);
+    }
+    match claude.save_chat() {
+        Ok(filename) => println!("Chat saved to {}", filename),
+        Err(e) => error!("Failed to save chat: {}", e),
+    }
     fs::remove_file("prompt.txt")?;
     info!("Program completed successfully");
     Ok(())
@@ -0,0 +1,256 @@
+use anyhow::{anyhow, bail, Result};
+
+/// The changes a unified diff makes to one file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilePatch {
+    /// Path the patch reads from, with the first component (`a/`) stripped. `None` when
+    /// the patch creates the file.
+    pub old_path: Option<String>,
+    /// Path the patch writes to, with the first component (`b/`) stripped. `None` when
+    /// the patch deletes the file.
+    pub new_path: Option<String>,
+    pub hunks: Vec<Hunk>,
+    /// Set by `\ No newline at end of file` after an added or context line.
+    no_newline_at_end: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    /// 1-based line in the original file where the hunk starts.
+    pub old_start: usize,
+    pub lines: Vec<HunkLine>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+impl Hunk {
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.as_str()),
+                HunkLine::Add(_) => None,
+            })
+            .collect()
+    }
+
+    fn new_lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().filter_map(|line| match line {
+            HunkLine::Context(text) | HunkLine::Add(text) => Some(text.as_str()),
+            HunkLine::Remove(_) => None,
+        })
+    }
+}
+
+impl FilePatch {
+    /// The path the patch applies to: the new path, or the old one for deletions.
+    pub fn target(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Number of added and removed lines.
+    pub fn line_counts(&self) -> (usize, usize) {
+        let lines = self.hunks.iter().flat_map(|hunk| &hunk.lines);
+        lines.fold((0, 0), |(added, removed), line| match line {
+            HunkLine::Add(_) => (added + 1, removed),
+            HunkLine::Remove(_) => (added, removed + 1),
+            HunkLine::Context(_) => (added, removed),
+        })
+    }
+
+    /// Applies the hunks to `original`. Each hunk is tried at the line its header names
+    /// first, then at the first later position where its context and removed lines match.
+    pub fn apply(&self, original: &str) -> Result<String> {
+        let lines: Vec<&str> = original.lines().collect();
+        let mut patched: Vec<&str> = Vec::with_capacity(lines.len());
+        let mut cursor = 0;
+        let mut reaches_end = false;
+
+        for hunk in &self.hunks {
+            let old_lines = hunk.old_lines();
+            let matches_at = |start: usize| {
+                start + old_lines.len() <= lines.len()
+                    && lines[start..start + old_lines.len()] == old_lines[..]
+            };
+            let expected = hunk.old_start.saturating_sub(1).max(cursor);
+            let start = if matches_at(expected) {
+                expected
+            } else {
+                (cursor..=lines.len())
+                    .find(|&start| matches_at(start))
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Hunk at line {} does not match {}",
+                            hunk.old_start,
+                            self.target()
+                        )
+                    })?
+            };
+
+            patched.extend(&lines[cursor..start]);
+            patched.extend(hunk.new_lines());
+            cursor = start + old_lines.len();
+            reaches_end = cursor == lines.len();
+        }
+        patched.extend(&lines[cursor..]);
+
+        let mut content = patched.join("\n");
+        let trailing_newline = if reaches_end {
+            !self.no_newline_at_end
+        } else {
+            original.ends_with('\n')
+        };
+        if trailing_newline && !content.is_empty() {
+            content.push('\n');
+        }
+        Ok(content)
+    }
+}
+
+/// Parses a unified diff, as produced by `diff -u` or `git diff`, into per-file patches.
+pub fn parse(patch: &str) -> Result<Vec<FilePatch>> {
+    let mut files: Vec<FilePatch> = Vec::new();
+    let mut lines = patch.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        if let Some(old_header) = line.strip_prefix("--- ") {
+            let new_header = lines
+                .next()
+                .and_then(|next| next.strip_prefix("+++ "))
+                .ok_or_else(|| anyhow!("Expected a +++ line after {}", line))?;
+            files.push(FilePatch {
+                old_path: parse_header_path(old_header),
+                new_path: parse_header_path(new_header),
+                hunks: Vec::new(),
+                no_newline_at_end: false,
+            });
+        } else if let Some(header) = line.strip_prefix("@@ ") {
+            let file = files
+                .last_mut()
+                .ok_or_else(|| anyhow!("Hunk before any file header: {}", line))?;
+            let (old_start, mut old_remaining, mut new_remaining) = parse_hunk_header(header)?;
+            let mut hunk = Hunk {
+                old_start,
+                lines: Vec::new(),
+            };
+            while old_remaining > 0 || new_remaining > 0 {
+                let Some(hunk_line) = lines.next() else {
+                    bail!("Patch ends in the middle of a hunk for {}", file.target());
+                };
+                let text = hunk_line.get(1..).unwrap_or_default();
+                match hunk_line.chars().next() {
+                    Some(' ') | None => {
+                        old_remaining = old_remaining.saturating_sub(1);
+                        new_remaining = new_remaining.saturating_sub(1);
+                        hunk.lines.push(HunkLine::Context(text.to_string()));
+                    }
+                    Some('-') => {
+                        old_remaining = old_remaining.saturating_sub(1);
+                        hunk.lines.push(HunkLine::Remove(text.to_string()));
+                    }
+                    Some('+') => {
+                        new_remaining = new_remaining.saturating_sub(1);
+                        hunk.lines.push(HunkLine::Add(text.to_string()));
+                    }
+                    Some('\\') => {}
+                    _ => bail!("Unexpected line in hunk: {}", hunk_line),
+                }
+            }
+            if lines.peek().is_some_and(|next| next.starts_with('\\'))
+                && !matches!(hunk.lines.last(), Some(HunkLine::Remove(_)))
+            {
+                file.no_newline_at_end = true;
+            }
+            file.hunks.push(hunk);
+        }
+    }
+
+    if files.is_empty() {
+        bail!("No file changes found in patch");
+    }
+    Ok(files)
+}
+
+/// Returns the path of a `---`/`+++` header without its timestamp and first component,
+/// or `None` for `/dev/null`.
+fn parse_header_path(header: &str) -> Option<String> {
+    let path = header.split('\t').next().unwrap_or(header).trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    Some(match path.split_once('/') {
+        Some((_, rest)) => rest.to_string(),
+        None => path.to_string(),
+    })
+}
+
+/// Parses `-start,count +start,count @@` into the old start and the old and new counts.
+fn parse_hunk_header(header: &str) -> Result<(usize, usize, usize)> {
+    let invalid = || anyhow!("Invalid hunk header: @@ {}", header);
+    let mut ranges = header.split_whitespace();
+    let mut parse_range = |prefix: char| -> Result<(usize, usize)> {
+        let range = ranges
+            .next()
+            .and_then(|range| range.strip_prefix(prefix))
+            .ok_or_else(invalid)?;
+        let (start, count) = range.split_once(',').unwrap_or((range, "1"));
+        Ok((
+            start.parse().map_err(|_| invalid())?,
+            count.parse().map_err(|_| invalid())?,
+        ))
+    };
+    let (old_start, old_count) = parse_range('-')?;
+    let (_, new_count) = parse_range('+')?;
+    Ok((old_start, old_count, new_count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "\
+diff --git a/src/lib.rs b/src/lib.rs
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,4 @@
+ fn a() {}
+-fn b() {}
++fn b() {
++    todo!()
++}
+ fn c() {}
+--- /dev/null
++++ b/src/new.rs
+@@ -0,0 +1 @@
++pub mod new;
+";
+
+    #[test]
+    fn test_parse_and_apply() {
+        let files = parse(PATCH).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].target(), "src/lib.rs");
+        assert_eq!(files[0].line_counts(), (3, 1));
+        assert_eq!(
+            files[0].apply("fn a() {}\nfn b() {}\nfn c() {}\n").unwrap(),
+            "fn a() {}\nfn b() {\n    todo!()\n}\nfn c() {}\n"
+        );
+
+        assert_eq!(files[1].old_path, None);
+        assert_eq!(files[1].apply("").unwrap(), "pub mod new;\n");
+    }
+
+    #[test]
+    fn test_apply_rejects_mismatched_context() {
+        let files = parse(PATCH).unwrap();
+        assert!(files[0].apply("fn a() {}\nfn x() {}\nfn c() {}\n").is_err());
+    }
+}
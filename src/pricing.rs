@@ -0,0 +1,122 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Tokens billed for one API response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct TokenUsage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    /// Input tokens written to the prompt cache.
+    pub cache_creation_input_tokens: u32,
+    /// Input tokens read from the prompt cache.
+    pub cache_read_input_tokens: u32,
+}
+
+impl TokenUsage {
+    /// Reads the `usage` object of an API response. Fields it lacks, such as the cache
+    /// counts when prompt caching is unused, count as zero.
+    pub fn from_json(usage: &Value) -> Self {
+        serde_json::from_value(usage.clone()).unwrap_or_default()
+    }
+}
+
+/// Anthropic API prices in USD per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPricing {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+    pub cache_write_per_million: f64,
+    pub cache_read_per_million: f64,
+}
+
+impl ModelPricing {
+    pub fn cost_usd(&self, usage: &TokenUsage) -> f64 {
+        (usage.input_tokens as f64 * self.input_per_million
+            + usage.output_tokens as f64 * self.output_per_million
+            + usage.cache_creation_input_tokens as f64 * self.cache_write_per_million
+            + usage.cache_read_input_tokens as f64 * self.cache_read_per_million)
+            / 1_000_000.0
+    }
+}
+
+/// Returns the published pricing for `model`. Unknown models are priced as
+/// Claude 3.5 Sonnet.
+pub fn default_pricing(model: &str) -> ModelPricing {
+    match model {
+        "claude-3-haiku-20240307" => ModelPricing {
+            input_per_million: 0.25,
+            output_per_million: 1.25,
+            cache_write_per_million: 0.30,
+            cache_read_per_million: 0.03,
+        },
+        "claude-3-opus-20240229" => ModelPricing {
+            input_per_million: 15.0,
+            output_per_million: 75.0,
+            cache_write_per_million: 18.75,
+            cache_read_per_million: 1.50,
+        },
+        _ => ModelPricing {
+            input_per_million: 3.0,
+            output_per_million: 15.0,
+            cache_write_per_million: 3.75,
+            cache_read_per_million: 0.30,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn test_cost_usd() {
+        let sonnet = default_pricing("claude-3-5-sonnet-20240620");
+        let usage = TokenUsage {
+            input_tokens: 1_000_000,
+            output_tokens: 100_000,
+            ..TokenUsage::default()
+        };
+        assert!((sonnet.cost_usd(&usage) - 4.5).abs() < 1e-9);
+
+        let cached = TokenUsage {
+            cache_creation_input_tokens: 1_000_000,
+            cache_read_input_tokens: 1_000_000,
+            ..TokenUsage::default()
+        };
+        assert!((sonnet.cost_usd(&cached) - 4.05).abs() < 1e-9);
+
+        let haiku = default_pricing("claude-3-haiku-20240307");
+        let usage = TokenUsage {
+            input_tokens: 2_000_000,
+            ..TokenUsage::default()
+        };
+        assert!((haiku.cost_usd(&usage) - 0.5).abs() < 1e-9);
+
+        assert_eq!(default_pricing("unknown-model"), sonnet);
+    }
+
+    #[test]
+    fn test_token_usage_from_json() {
+        assert_eq!(
+            TokenUsage::from_json(&json!({ "input_tokens": 10, "output_tokens": 5 })),
+            TokenUsage {
+                input_tokens: 10,
+                output_tokens: 5,
+                ..TokenUsage::default()
+            }
+        );
+        assert_eq!(
+            TokenUsage::from_json(&json!({
+                "input_tokens": 10,
+                "output_tokens": 5,
+                "cache_creation_input_tokens": 3,
+                "cache_read_input_tokens": 7
+            }))
+            .cache_read_input_tokens,
+            7
+        );
+    }
+}
@@ -1,3 +1,7 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use std::str::FromStr;
+
 pub const BASE_SYSTEM_PROMPT: &str = r#"
 You are Claude, an AI assistant powered by Anthropic's Claude-3.5-Sonnet model, specializing in software development. Your capabilities include:
 
@@ -62,3 +66,209 @@ Answer the user's request using relevant tools (if they are available). Before c
 
 Do not reflect on the quality of the returned search results in your response.
 "#;
+
+pub const ARCHITECT_PROMPT: &str = r#"
+You are acting as a software architect. Focus on the overall structure of the system before individual lines of code:
+- Identify module boundaries, ownership of data, and the dependencies between components.
+- Prefer designs that keep coupling low and make future changes cheap.
+- Explain the trade-offs of each proposed design and call out what would need to change if requirements grow.
+- Only make code edits once the structure of the change is clear and agreed.
+"#;
+
+pub const SECURITY_REVIEWER_PROMPT: &str = r#"
+You are acting as a security reviewer. Examine every change with an attacker's mindset:
+- Look for unvalidated input, path traversal, injection, unsafe deserialization and leaked secrets.
+- Check error handling paths, since failures are where invariants are most often broken.
+- Flag risky dependencies and unsafe code, and explain the impact of each finding.
+- Prefer the smallest change that removes a vulnerability over broad rewrites.
+"#;
+
+pub const DOCUMENTATION_WRITER_PROMPT: &str = r#"
+You are acting as a documentation writer. Your goal is to make the project easy to understand and use:
+- Write clear doc comments, READMEs and usage examples that match the existing tone of the project.
+- Describe what code does and why, not how every line works.
+- Keep examples minimal, correct and runnable.
+- Avoid changing behaviour; only touch code when the documentation requires it.
+"#;
+
+pub const TEST_ENGINEER_PROMPT: &str = r#"
+You are acting as a test engineer. Your goal is to increase confidence that the code is correct:
+- Identify untested behaviour, edge cases and failure modes before writing tests.
+- Place tests where the project already keeps them and follow the existing test style.
+- Prefer small, deterministic tests over large integration tests that depend on the network.
+- When a test exposes a bug, describe it clearly before proposing a fix.
+"#;
+
+/// System prompt for the code editor model. Placeholders in braces are filled in by
+/// `EditorPromptVersion::render`.
+pub const EDITOR_PROMPT_V1: &str = r#"
+You are an AI coding agent that generates edit instructions for code files. Your task is to analyze the provided code and generate SEARCH/REPLACE blocks for necessary changes. Follow these steps:
+
+1. Review the entire file content to understand the context:
+{file_content}
+
+2. Carefully analyze the specific instructions:
+{instructions}
+
+3. Take into account the overall project context:
+{project_context}
+
+4. Consider the memory of previous edits:
+{memory_context}
+
+5. Consider the full context of all files in the project:
+{full_file_contents_context}
+
+6. Generate SEARCH/REPLACE blocks for each necessary change. Each block should:
+   - Include enough context to uniquely identify the code to be changed
+   - Provide the exact replacement code, maintaining correct indentation and formatting
+   - Focus on specific, targeted changes rather than large, sweeping modifications
+
+7. Ensure that your SEARCH/REPLACE blocks:
+   - Address all relevant aspects of the instructions
+   - Maintain or enhance code readability and efficiency
+   - Consider the overall structure and purpose of the code
+   - Follow best practices and coding standards for the language
+   - Maintain consistency with the project context and previous edits
+   - Take into account the full context of all files in the project
+
+IMPORTANT: RETURN ONLY THE SEARCH/REPLACE BLOCKS. NO EXPLANATIONS OR COMMENTS.
+USE THE FOLLOWING FORMAT FOR EACH BLOCK:
+
+<SEARCH>
+Code to be replaced
+</SEARCH>
+<REPLACE>
+New code to insert
+</REPLACE>
+
+If no changes are needed, return an empty list.
+"#;
+
+/// Versions of the code editor system prompt, so prompt changes can be compared and
+/// rolled back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EditorPromptVersion {
+    #[default]
+    V1,
+}
+
+impl EditorPromptVersion {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EditorPromptVersion::V1 => "v1",
+        }
+    }
+
+    pub fn template(&self) -> &'static str {
+        match self {
+            EditorPromptVersion::V1 => EDITOR_PROMPT_V1,
+        }
+    }
+
+    /// Fills the `{name}` placeholders of the template in a single pass, so braces in
+    /// the substituted values are left untouched.
+    pub fn render(&self, vars: &[(&str, &str)]) -> String {
+        let placeholder = Regex::new(r"\{(\w+)\}").unwrap();
+        placeholder
+            .replace_all(self.template(), |caps: &regex::Captures| {
+                vars.iter()
+                    .find(|(name, _)| *name == &caps[1])
+                    .map_or_else(|| caps[0].to_string(), |(_, value)| value.to_string())
+            })
+            .into_owned()
+    }
+}
+
+impl FromStr for EditorPromptVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(version: &str) -> Result<Self> {
+        match version.to_lowercase().trim_start_matches('v') {
+            "1" => Ok(EditorPromptVersion::V1),
+            _ => Err(anyhow!("Unknown editor prompt version: {}", version)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Persona {
+    SoftwareEngineer,
+    Architect,
+    SecurityReviewer,
+    DocumentationWriter,
+    TestEngineer,
+}
+
+impl Persona {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Persona::SoftwareEngineer => "Software Engineer",
+            Persona::Architect => "Architect",
+            Persona::SecurityReviewer => "Security Reviewer",
+            Persona::DocumentationWriter => "Documentation Writer",
+            Persona::TestEngineer => "Test Engineer",
+        }
+    }
+
+    pub fn base_system_prompt(&self) -> String {
+        let emphasis = match self {
+            Persona::SoftwareEngineer => return BASE_SYSTEM_PROMPT.to_string(),
+            Persona::Architect => ARCHITECT_PROMPT,
+            Persona::SecurityReviewer => SECURITY_REVIEWER_PROMPT,
+            Persona::DocumentationWriter => DOCUMENTATION_WRITER_PROMPT,
+            Persona::TestEngineer => TEST_ENGINEER_PROMPT,
+        };
+        format!("{}\n{}", BASE_SYSTEM_PROMPT, emphasis)
+    }
+}
+
+impl FromStr for Persona {
+    type Err = anyhow::Error;
+
+    /// Parses a persona name such as `"security-reviewer"` or `"Security Reviewer"`.
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_lowercase().replace(['-', '_'], " ").trim() {
+            "software engineer" => Ok(Persona::SoftwareEngineer),
+            "architect" => Ok(Persona::Architect),
+            "security reviewer" => Ok(Persona::SecurityReviewer),
+            "documentation writer" => Ok(Persona::DocumentationWriter),
+            "test engineer" => Ok(Persona::TestEngineer),
+            _ => Err(anyhow!("Unknown persona: {}", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_editor_prompt_render() {
+        let prompt = EditorPromptVersion::V1.render(&[
+            ("file_content", "fn main() { let x = \"{instructions}\"; }"),
+            ("instructions", "Rename x"),
+        ]);
+        assert!(prompt.contains("fn main() { let x = \"{instructions}\"; }"));
+        assert!(prompt.contains("Rename x"));
+        assert!(prompt.contains("{project_context}"));
+        assert_eq!(
+            "v1".parse::<EditorPromptVersion>().unwrap(),
+            EditorPromptVersion::V1
+        );
+        assert!("v9".parse::<EditorPromptVersion>().is_err());
+    }
+
+    #[test]
+    fn test_persona_from_str() {
+        assert_eq!(
+            "security-reviewer".parse::<Persona>().unwrap(),
+            Persona::SecurityReviewer
+        );
+        assert_eq!(
+            "Test Engineer".parse::<Persona>().unwrap(),
+            Persona::TestEngineer
+        );
+        assert!("manager".parse::<Persona>().is_err());
+    }
+}
@@ -0,0 +1,375 @@
+use anyhow::{anyhow, Context, Result};
+use futures_util::future::try_join_all;
+use scraper::{Html, Selector};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufWriter, Write};
+
+/// Used when `QDRANT_URL` is unset: a local Qdrant serving its REST API.
+const DEFAULT_QDRANT_URL: &str = "http://localhost:6333";
+/// Collection the Rust Book is indexed into.
+pub const DEFAULT_COLLECTION_NAME: &str = "rust_book";
+/// Table of contents the book's chapter pages are scraped from.
+const RUST_BOOK_URL: &str = "https://doc.rust-lang.org/book/";
+const EXPORT_PAGE_SIZE: u32 = 256;
+/// Passages retrieved per query.
+const SEARCH_LIMIT: usize = 3;
+
+const RUST_BOOK_PROMPT: &str = r#"
+        {{#chat}}
+            {{#system}}
+            You are an expert Rust programmer and teacher. You have been given a question about Rust and some relevant information from the Rust Book. Use this information to provide a comprehensive and accurate answer to the user's question.
+            {{/system}}
+
+            {{#user}}
+            {{user_query}}
+            {{/user}}
+
+            {{#system}}
+            Based on the retrieved information from the Rust Book, here are the relevant passages:
+
+            {{relevant_info}}
+
+            Please provide a detailed answer to the user's question, integrating insights from these passages and your expert knowledge of Rust.
+            {{/system}}
+        {{/chat}}
+        "#;
+
+/// The Qdrant collection holding the indexed Rust Book, managed through Qdrant's REST
+/// API. Embedding text is left to the caller, so no model is tied to the index.
+#[derive(Debug, Clone)]
+pub struct RustDocsIndex {
+    client: reqwest::Client,
+    base_url: String,
+    collection_name: String,
+}
+
+impl RustDocsIndex {
+    /// Connects to the Qdrant at `QDRANT_URL`, or a local one if it is unset.
+    pub fn new(collection_name: &str) -> Self {
+        let base_url =
+            std::env::var("QDRANT_URL").unwrap_or_else(|_| DEFAULT_QDRANT_URL.to_string());
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            collection_name: collection_name.to_string(),
+        }
+    }
+
+    fn collection_url(&self, path: &str) -> String {
+        format!(
+            "{}/collections/{}{}",
+            self.base_url, self.collection_name, path
+        )
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<Value> {
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach Qdrant at {}", self.base_url))?;
+        let body = response.error_for_status()?.text().await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    async fn post(&self, path: &str, body: Value) -> Result<Value> {
+        self.send(
+            self.client
+                .post(self.collection_url(path))
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.to_string()),
+        )
+        .await
+    }
+
+    /// Scrapes every chapter of the Rust Book, embeds each with `embed` and inserts
+    /// them into the collection, which must already exist with a matching vector size.
+    /// Returns the number of pages indexed.
+    pub async fn scrape_and_insert<F, Fut>(&self, embed: F) -> Result<usize>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<Vec<f32>>>,
+    {
+        let contents = self.fetch_html(RUST_BOOK_URL).await?;
+        let pages = book_page_urls(&contents, RUST_BOOK_URL);
+
+        let mut points = Vec::with_capacity(pages.len());
+        for (id, page) in pages.iter().enumerate() {
+            let content = extract_main_text(&self.fetch_html(page).await?)
+                .ok_or_else(|| anyhow!("No main content in {}", page))?;
+            let vector = embed(content.clone()).await?;
+            points.push(json!({
+                "id": id,
+                "vector": vector,
+                "payload": { "content": content, "url": page },
+            }));
+        }
+
+        self.send(
+            self.client
+                .put(self.collection_url("/points"))
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(json!({ "points": points }).to_string()),
+        )
+        .await?;
+        Ok(points.len())
+    }
+
+    async fn fetch_html(&self, url: &str) -> Result<String> {
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .with_context(|| format!("Failed to fetch {}", url))?;
+        Ok(response.error_for_status()?.text().await?)
+    }
+
+    /// Returns the Rust Book prompt for `query`, filled with the passages closest to it.
+    pub async fn query_and_get_prompt<F, Fut>(&self, query: &str, embed: F) -> Result<String>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<Vec<f32>>>,
+    {
+        let results = self.search(embed(query.to_string()).await?).await?;
+        Ok(combine_search_results(&[query], vec![results], false))
+    }
+
+    /// Returns the points closest to `vector`, as found in a search response's `result`.
+    pub async fn search(&self, vector: Vec<f32>) -> Result<Vec<Value>> {
+        let response = self
+            .post(
+                "/points/search",
+                json!({ "vector": vector, "limit": SEARCH_LIMIT, "with_payload": true }),
+            )
+            .await?;
+        response["result"]
+            .as_array()
+            .cloned()
+            .ok_or_else(|| anyhow!("Unexpected Qdrant response: missing result"))
+    }
+
+    /// Searches for several queries at once, embedding each with `embed`. The searches run
+    /// concurrently and their passages are combined into one prompt, skipping passages
+    /// already found by an earlier query when `deduplicate` is set. The returned vec holds
+    /// that single combined prompt.
+    pub async fn query_batch<F, Fut>(
+        &self,
+        queries: Vec<&str>,
+        deduplicate: bool,
+        embed: F,
+    ) -> Result<Vec<String>>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<Vec<f32>>>,
+    {
+        let results = try_join_all(queries.iter().map(|query| {
+            let embedding = embed(query.to_string());
+            async move { self.search(embedding.await?).await }
+        }))
+        .await?;
+
+        Ok(vec![combine_search_results(&queries, results, deduplicate)])
+    }
+
+    /// Deletes the collection and everything indexed in it.
+    pub async fn delete_collection(&self) -> Result<()> {
+        self.send(self.client.delete(self.collection_url("")))
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes the collection if it exists and creates it again, empty, for vectors of
+    /// `vector_size` dimensions.
+    pub async fn recreate_collection(&self, vector_size: usize) -> Result<()> {
+        // Qdrant answers a delete of a missing collection with `"result": false`, not an error.
+        self.delete_collection().await?;
+        self.send(
+            self.client
+                .put(self.collection_url(""))
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(collection_config(vector_size).to_string()),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Writes every point in the collection to `output_path` as newline-delimited JSON,
+    /// one `{"id", "payload", "vector"}` object per line. Returns the number of records.
+    pub async fn export_collection(&self, output_path: &str) -> Result<usize> {
+        let mut writer = BufWriter::new(
+            File::create(output_path)
+                .with_context(|| format!("Failed to create {}", output_path))?,
+        );
+        let mut offset = Value::Null;
+        let mut exported = 0;
+
+        loop {
+            let response = self
+                .post(
+                    "/points/scroll",
+                    json!({
+                        "offset": offset,
+                        "limit": EXPORT_PAGE_SIZE,
+                        "with_payload": true,
+                        "with_vector": true,
+                    }),
+                )
+                .await?;
+            let (records, next_offset) = parse_scroll_page(&response)?;
+            for record in &records {
+                writeln!(writer, "{}", record)?;
+            }
+            exported += records.len();
+
+            if next_offset.is_null() {
+                break;
+            }
+            offset = next_offset;
+        }
+
+        writer.flush()?;
+        Ok(exported)
+    }
+}
+
+/// Renders the Rust Book prompt for `queries` from the points each one found, in query
+/// order, dropping points seen for an earlier query when `deduplicate` is set.
+fn combine_search_results(queries: &[&str], results: Vec<Vec<Value>>, deduplicate: bool) -> String {
+    let mut seen_ids = HashSet::new();
+    let passages = results
+        .into_iter()
+        .flatten()
+        .filter(|point| !deduplicate || seen_ids.insert(point["id"].to_string()))
+        .filter_map(|point| point.get("payload").map(|payload| payload.to_string()))
+        .collect::<Vec<_>>();
+
+    RUST_BOOK_PROMPT
+        .replace("{{user_query}}", &queries.join("\n"))
+        .replace("{{relevant_info}}", &passages.join("\n"))
+}
+
+/// The chapter links in the book's table of contents, resolved against `base_url`.
+fn book_page_urls(html: &str, base_url: &str) -> Vec<String> {
+    let selector = Selector::parse("ol.chapter li a").expect("valid selector");
+    Html::parse_document(html)
+        .select(&selector)
+        .filter_map(|element| element.value().attr("href"))
+        .map(|href| format!("{}{}", base_url, href))
+        .collect()
+}
+
+/// The text of the page's `<main>` element, if it has one.
+fn extract_main_text(html: &str) -> Option<String> {
+    let selector = Selector::parse("main").expect("valid selector");
+    Html::parse_document(html)
+        .select(&selector)
+        .next()
+        .map(|main| main.text().collect::<Vec<_>>().join(" "))
+}
+
+fn collection_config(vector_size: usize) -> Value {
+    json!({ "vectors": { "size": vector_size, "distance": "Cosine" } })
+}
+
+/// Splits a scroll response into export records and the offset of the next page, which
+/// is null on the last page.
+fn parse_scroll_page(response: &Value) -> Result<(Vec<Value>, Value)> {
+    let result = response
+        .get("result")
+        .ok_or_else(|| anyhow!("Unexpected Qdrant response: missing result"))?;
+    let records = result["points"]
+        .as_array()
+        .ok_or_else(|| anyhow!("Unexpected Qdrant response: missing points"))?
+        .iter()
+        .map(|point| {
+            json!({
+                "id": point["id"],
+                "payload": point["payload"],
+                "vector": point["vector"],
+            })
+        })
+        .collect();
+    Ok((records, result["next_page_offset"].clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combine_search_results_deduplicates_by_id() {
+        let ownership = json!({ "id": 1, "score": 0.9, "payload": { "content": "Ownership" } });
+        let borrowing = json!({ "id": 2, "score": 0.8, "payload": { "content": "Borrowing" } });
+        let results = vec![
+            vec![ownership.clone(), borrowing.clone()],
+            vec![json!({ "id": 1, "score": 0.7, "payload": { "content": "Ownership" } })],
+        ];
+
+        let combined = combine_search_results(&["ownership", "borrowing"], results.clone(), true);
+        assert!(combined.contains("ownership\nborrowing"));
+        assert_eq!(combined.matches("Ownership").count(), 1);
+        assert_eq!(combined.matches("Borrowing").count(), 1);
+
+        let combined = combine_search_results(&["ownership", "borrowing"], results, false);
+        assert_eq!(combined.matches("Ownership").count(), 2);
+    }
+
+    #[test]
+    fn test_book_page_urls_and_main_text() {
+        let toc = r#"<html><body><ol class="chapter">
+            <li><a href="ch01-00-getting-started.html">Getting Started</a></li>
+            <li><a href="ch04-00-understanding-ownership.html">Ownership</a></li>
+        </ol></body></html>"#;
+        assert_eq!(
+            book_page_urls(toc, RUST_BOOK_URL),
+            vec![
+                "https://doc.rust-lang.org/book/ch01-00-getting-started.html",
+                "https://doc.rust-lang.org/book/ch04-00-understanding-ownership.html",
+            ]
+        );
+
+        let page = "<html><nav>Menu</nav><main><h1>Ownership</h1><p>Each value has an owner.</p></main></html>";
+        assert_eq!(
+            extract_main_text(page).unwrap(),
+            "Ownership Each value has an owner."
+        );
+        assert!(extract_main_text("<html><p>No main</p></html>").is_none());
+    }
+
+    #[test]
+    fn test_collection_config() {
+        assert_eq!(
+            collection_config(384),
+            json!({ "vectors": { "size": 384, "distance": "Cosine" } })
+        );
+    }
+
+    #[test]
+    fn test_parse_scroll_page() {
+        let response = json!({
+            "result": {
+                "points": [
+                    {
+                        "id": 7,
+                        "payload": { "content": "Ownership" },
+                        "vector": [0.25, 0.5],
+                        "shard_key": null
+                    }
+                ],
+                "next_page_offset": 8
+            },
+            "status": "ok"
+        });
+
+        let (records, next_offset) = parse_scroll_page(&response).unwrap();
+        assert_eq!(
+            records,
+            vec![json!({ "id": 7, "payload": { "content": "Ownership" }, "vector": [0.25, 0.5] })]
+        );
+        assert_eq!(next_offset, json!(8));
+        assert!(parse_scroll_page(&json!({ "status": "error" })).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use anyhow::Result;
+
+use crate::conversation_manager::Message;
+
+/// Persists conversation messages as they are committed to history, keyed by session ID.
+pub trait ConversationStore: std::fmt::Debug + Send + Sync {
+    fn save_message(&self, session_id: &str, message: &Message) -> Result<()>;
+    /// Returns the session's messages in the order they were saved.
+    fn load_session(&self, session_id: &str) -> Result<Vec<Message>>;
+    /// Returns the IDs of all stored sessions, oldest first.
+    fn list_sessions(&self) -> Result<Vec<String>>;
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteConversationStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use anyhow::{anyhow, Result};
+    use chrono::Utc;
+    use rusqlite::{params, Connection};
+    use std::sync::Mutex;
+
+    use super::ConversationStore;
+    use crate::conversation_manager::Message;
+
+    const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS messages (
+        id INTEGER PRIMARY KEY,
+        session_id TEXT NOT NULL,
+        role TEXT NOT NULL,
+        content_json TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    )";
+
+    /// Stores messages in a single SQLite table, which copes with long histories better
+    /// than rewriting a JSON file on every save.
+    #[derive(Debug)]
+    pub struct SqliteConversationStore {
+        connection: Mutex<Connection>,
+    }
+
+    impl SqliteConversationStore {
+        /// Opens the database at `path`, creating it and the messages table if needed.
+        pub fn open(path: &str) -> Result<Self> {
+            let connection = Connection::open(path)
+                .map_err(|e| anyhow!("Failed to open conversation database {}: {}", path, e))?;
+            Self::with_connection(connection)
+        }
+
+        pub fn open_in_memory() -> Result<Self> {
+            Self::with_connection(Connection::open_in_memory()?)
+        }
+
+        fn with_connection(connection: Connection) -> Result<Self> {
+            connection.execute(SCHEMA, [])?;
+            Ok(Self {
+                connection: Mutex::new(connection),
+            })
+        }
+
+        fn connection(&self) -> Result<std::sync::MutexGuard<'_, Connection>> {
+            self.connection
+                .lock()
+                .map_err(|_| anyhow!("Conversation database lock poisoned"))
+        }
+    }
+
+    impl ConversationStore for SqliteConversationStore {
+        fn save_message(&self, session_id: &str, message: &Message) -> Result<()> {
+            self.connection()?.execute(
+                "INSERT INTO messages (session_id, role, content_json, created_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    session_id,
+                    message.role,
+                    serde_json::to_string(&message.content)?,
+                    Utc::now().to_rfc3339()
+                ],
+            )?;
+            Ok(())
+        }
+
+        fn load_session(&self, session_id: &str) -> Result<Vec<Message>> {
+            let connection = self.connection()?;
+            let mut statement = connection.prepare(
+                "SELECT role, content_json FROM messages WHERE session_id = ?1 ORDER BY id",
+            )?;
+            let rows = statement.query_map(params![session_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
+            rows.map(|row| {
+                let (role, content_json) = row?;
+                Ok(Message::new(&role, serde_json::from_str(&content_json)?))
+            })
+            .collect()
+        }
+
+        fn list_sessions(&self) -> Result<Vec<String>> {
+            let connection = self.connection()?;
+            let mut statement = connection
+                .prepare("SELECT session_id FROM messages GROUP BY session_id ORDER BY MIN(id)")?;
+            let sessions = statement
+                .query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(sessions)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite_store_round_trip() {
+        let store = SqliteConversationStore::open_in_memory().unwrap();
+        let message = |role: &str, text: &str| Message::text(role, text);
+        store.save_message("b", &message("user", "Hello")).unwrap();
+        store.save_message("a", &message("user", "Other")).unwrap();
+        store
+            .save_message("b", &message("assistant", "Hi"))
+            .unwrap();
+
+        let loaded = store.load_session("b").unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[1].role, "assistant");
+        assert_eq!(loaded[1].content.to_plain_text(), "Hi");
+        assert_eq!(store.list_sessions().unwrap(), vec!["b", "a"]);
+        assert!(store.load_session("missing").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+const TAVILY_SEARCH_URL: &str = "https://api.tavily.com/search";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TavilyResult {
+    pub title: String,
+    pub url: String,
+    /// The excerpt of the page Tavily matched, returned as `content` by the API.
+    #[serde(rename = "content")]
+    pub snippet: String,
+}
+
+/// Searches the web with Tavily, reading the API key from `TAVILY_API_KEY`.
+pub async fn tavily_search(query: &str, max_results: u8) -> Result<Vec<TavilyResult>> {
+    let api_key =
+        std::env::var("TAVILY_API_KEY").context("Failed to get TAVILY_API_KEY from environment")?;
+    let body = json!({
+        "api_key": api_key,
+        "query": query,
+        "max_results": max_results,
+    });
+
+    let response = reqwest::Client::new()
+        .post(TAVILY_SEARCH_URL)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach Tavily for query: {}", query))?;
+    let body = response.error_for_status()?.text().await?;
+    parse_search_response(&serde_json::from_str(&body)?)
+}
+
+fn parse_search_response(response: &Value) -> Result<Vec<TavilyResult>> {
+    let results = response
+        .get("results")
+        .ok_or_else(|| anyhow!("Unexpected Tavily response: missing results"))?;
+    Ok(serde_json::from_value(results.clone())?)
+}
+
+/// Formats results as a numbered list with the URL and snippet under each title.
+pub fn format_results(results: &[TavilyResult]) -> String {
+    results
+        .iter()
+        .enumerate()
+        .map(|(i, result)| {
+            format!(
+                "{}. {}\n   {}\n   {}",
+                i + 1,
+                result.title,
+                result.url,
+                result.snippet
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format_search_response() {
+        let response = json!({
+            "query": "tokio select",
+            "results": [
+                {
+                    "title": "tokio::select",
+                    "url": "https://docs.rs/tokio/latest/tokio/macro.select.html",
+                    "content": "Waits on multiple concurrent branches.",
+                    "score": 0.98
+                }
+            ]
+        });
+
+        let results = parse_search_response(&response).unwrap();
+        assert_eq!(
+            format_results(&results),
+            "1. tokio::select\n   https://docs.rs/tokio/latest/tokio/macro.select.html\n   Waits on multiple concurrent branches."
+        );
+        assert!(parse_search_response(&json!({ "error": "Unauthorized" })).is_err());
+    }
+}
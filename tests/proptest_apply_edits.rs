@@ -0,0 +1,87 @@
+#![cfg(feature = "claude")]
+
+use anthropic_sdk::Client;
+use claude_engineer_rs::tools::{EditInstruction, SearchMode, ToolExecutor};
+use proptest::prelude::*;
+use std::fs;
+use tempfile::tempdir;
+
+/// Generates file content whose lines are unique and never contain one another, so a
+/// SEARCH block sampled from it matches exactly one location.
+fn content_lines() -> impl Strategy<Value = Vec<String>> {
+    prop::collection::vec("[a-z ]{0,12}", 1..60).prop_map(|words| {
+        words
+            .into_iter()
+            .enumerate()
+            .map(|(i, word)| format!("line{:04}_{}", i, word))
+            .collect()
+    })
+}
+
+fn edit_case() -> impl Strategy<Value = (Vec<String>, usize, usize, Vec<String>)> {
+    content_lines().prop_flat_map(|lines| {
+        let len = lines.len();
+        (Just(lines), 0..len)
+            .prop_flat_map(move |(lines, start)| {
+                (Just(lines), Just(start), 1..=(len - start).min(5))
+            })
+            .prop_flat_map(|(lines, start, search_len)| {
+                (
+                    Just(lines),
+                    Just(start),
+                    Just(search_len),
+                    prop::collection::vec("[A-Z]{1,10}", 1..6),
+                )
+            })
+            .prop_map(|(lines, start, search_len, replace)| {
+                let replace = replace
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, word)| format!("NEW{:02}_{}", i, word))
+                    .collect();
+                (lines, start, search_len, replace)
+            })
+    })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn apply_edits_replaces_sampled_search_block(
+        (lines, start, search_len, replace_lines) in edit_case()
+    ) {
+        let original = lines.join("\n");
+        let search = lines[start..start + search_len].join("\n");
+        let replace = replace_lines.join("\n");
+
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("prop.txt");
+        fs::write(&file_path, &original).unwrap();
+
+        let mut executor = ToolExecutor::new(Client::new()).unwrap();
+        executor.set_auto_apply(true);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let result = runtime
+            .block_on(executor.apply_edits(
+                file_path.to_str().unwrap(),
+                vec![EditInstruction {
+                    search: search.clone(),
+                    replace: replace.clone(),
+                    search_mode: SearchMode::Exact,
+                }],
+                &original,
+            ))
+            .unwrap();
+
+        prop_assert!(result.changes_made);
+        prop_assert!(result.failed_edits.is_empty(), "failed edits: {}", result.failed_summary());
+        prop_assert!(result.edited_content.contains(&replace));
+        prop_assert!(!result.edited_content.contains(&search));
+        prop_assert_eq!(
+            result.edited_content.lines().count(),
+            lines.len() - search_len + replace_lines.len()
+        );
+    }
+}